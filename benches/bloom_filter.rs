@@ -0,0 +1,123 @@
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
+use linked_list_1::blocked_bloom_filter::BlockedBloomFilter;
+use linked_list_1::bloom_filter::BloomFilter;
+
+// Large enough that the filters don't fit in cache, which is where blocking
+// pays off.
+const SIZES: [usize; 2] = [100_000, 10_000_000];
+const FP_RATE: f64 = 0.01;
+const OPS: u64 = 10_000;
+
+fn insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert");
+    group.throughput(Throughput::Elements(OPS));
+    for size in SIZES {
+        let mut plain = BloomFilter::<u64>::new(size, FP_RATE);
+        group.bench_with_input(BenchmarkId::new("BloomFilter", size), &size, |b, _| {
+            b.iter(|| {
+                for i in 0..OPS {
+                    plain.insert(black_box(&i));
+                }
+            })
+        });
+        let mut blocked = BlockedBloomFilter::<u64>::new(size, FP_RATE);
+        group.bench_with_input(
+            BenchmarkId::new("BlockedBloomFilter", size),
+            &size,
+            |b, _| {
+                b.iter(|| {
+                    for i in 0..OPS {
+                        blocked.insert(black_box(&i));
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn get(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get");
+    group.throughput(Throughput::Elements(OPS));
+    for size in SIZES {
+        let mut plain = BloomFilter::<u64>::new(size, FP_RATE);
+        let mut blocked = BlockedBloomFilter::<u64>::new(size, FP_RATE);
+        for i in (0..size as u64).step_by(2) {
+            plain.insert(&i);
+            blocked.insert(&i);
+        }
+        group.bench_with_input(BenchmarkId::new("BloomFilter", size), &size, |b, _| {
+            b.iter(|| (0..OPS).filter(|i| plain.get(black_box(i))).count())
+        });
+        group.bench_with_input(
+            BenchmarkId::new("BlockedBloomFilter", size),
+            &size,
+            |b, _| b.iter(|| (0..OPS).filter(|i| blocked.get(black_box(i))).count()),
+        );
+    }
+    group.finish();
+}
+
+// Batches only pay off when the probes miss the cache, so the batched calls
+// are measured on a filter of about 60 MB, well past the last level cache.
+const BATCH_SIZE: usize = 50_000_000;
+
+fn mix(i: u64) -> u64 {
+    i.wrapping_mul(0x9e37_79b9_7f4a_7c15)
+}
+
+// The batched calls against the scalar loops they replace. Every iteration
+// gets keys none of the others used, so their words aren't cached yet.
+fn batch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("batch");
+    group.throughput(Throughput::Elements(OPS));
+    let mut filter = BloomFilter::<u64>::new(BATCH_SIZE, FP_RATE);
+    let half: Vec<u64> = (0..BATCH_SIZE as u64 / 2).map(mix).collect();
+    filter.insert_many(&half);
+    drop(half);
+
+    let mut next = 1 << 40;
+    let mut fresh = || {
+        let keys: Vec<u64> = (next..next + OPS).map(mix).collect();
+        next += OPS;
+        keys
+    };
+    group.bench_function("insert", |b| {
+        b.iter_batched(
+            &mut fresh,
+            |keys| {
+                for key in &keys {
+                    filter.insert(key);
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("insert_many", |b| {
+        b.iter_batched(
+            &mut fresh,
+            |keys| filter.insert_many(&keys),
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("get", |b| {
+        b.iter_batched(
+            &mut fresh,
+            |keys| keys.iter().map(|key| filter.get(key)).collect::<Vec<_>>(),
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("contains_many", |b| {
+        b.iter_batched(
+            &mut fresh,
+            |keys| filter.contains_many(&keys),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, insert, get, batch);
+criterion_main!(benches);
@@ -1,8 +1,19 @@
+use crate::bloom_filter_builder::BloomFilterBuilder;
+use crate::bloom_hasher::{BloomHasher, HashStrategy};
+use crate::serialization::{
+    checksum_words, from_slice, read_array, read_words, write_words, DecodeError,
+};
+use bitvec::prelude::*;
+use std::error::Error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
 
 // Optimal number of bits
 // m bits = -(n * ln(p))/ ln(2)^2
 pub fn num_bits(size: usize, fp_rate: f64) -> usize {
-    let num = -1.0f64 * size as f64 * fp_rate.ln();
+    let num = -(size as f64) * fp_rate.ln();
     let den = 2.0f64.ln().powf(2.0);
     (num / den).ceil() as usize
 }
@@ -12,42 +23,731 @@ pub fn num_bits(size: usize, fp_rate: f64) -> usize {
 pub fn num_hashes(m: usize, n: usize) -> usize {
     ((m as f64 / n as f64) * 2.0f64.ln()).ceil() as usize
 }
+// The inverse of num_bits: how many items m bits hold at the given rate
+// n = -(m * ln(2)^2) / ln(p)
+pub fn num_items(m: usize, fp_rate: f64) -> usize {
+    let num = -(m as f64) * 2.0f64.ln().powf(2.0);
+    (num / fp_rate.ln()).floor() as usize
+}
+// False positive rate of m bits and k hashes holding n items
+// p = (1 - e^(-k * n / m))^k
+pub fn fp_rate(m: usize, k: usize, n: usize) -> f64 {
+    let k = k as f64;
+    (1.0 - (-k * n as f64 / m as f64).exp()).powf(k)
+}
+
+// Keys hashed per batch by the *_many methods. Enough to keep a few dozen
+// cache misses in flight with the usual hash counts while the positions stay
+// in L1.
+const BATCH: usize = 16;
+
+// Asks the CPU to start loading `word` into cache. Only a hint, and nothing
+// at all on targets without a stable prefetch intrinsic.
+#[inline(always)]
+fn prefetch(word: &u64) {
+    #[cfg(target_arch = "x86_64")]
+    // Safety: prefetching never faults, and `word` is a valid reference anyway.
+    unsafe {
+        use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+        _mm_prefetch::<_MM_HINT_T0>(word as *const u64 as *const i8);
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = word;
+}
+
+// Up to BATCH keys stored back to back in one buffer. The *_many methods
+// reuse a single one for every batch, so no key needs an allocation of its
+// own.
+#[derive(Default)]
+struct KeyBatch {
+    bytes: Vec<u8>,
+    ends: Vec<usize>,
+}
+
+impl KeyBatch {
+    fn clear(&mut self) {
+        self.bytes.clear();
+        self.ends.clear();
+    }
+
+    fn push(&mut self, key: &[u8]) {
+        self.bytes.extend_from_slice(key);
+        self.ends.push(self.bytes.len());
+    }
+
+    fn push_key<T: Hash + ?Sized>(&mut self, value: &T) {
+        KeyBytes::append(value, &mut self.bytes);
+        self.ends.push(self.bytes.len());
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &[u8]> {
+        self.ends.iter().scan(0, |start, &end| {
+            let key = &self.bytes[*start..end];
+            *start = end;
+            Some(key)
+        })
+    }
+}
+
+// Collects the bytes a `Hash` impl writes so any hashable key can be fed to
+// the byte oriented hash functions. Integers are written little endian so the
+// same key maps to the same bits on every platform. `str` and `[u8]` keys (and
+// `String`, `Vec<u8>`) come out as their raw bytes, without the terminator or
+// length prefix `Hash` adds, so they land on the same bits as insert_bytes.
+#[derive(Default)]
+pub(crate) struct KeyBytes {
+    bytes: Vec<u8>,
+    shape: Shape,
+}
+
+// The writes seen so far, enough to recognise the two raw byte shapes:
+// `str` writes its bytes then 0xff, `[u8]` writes its length then its bytes.
+#[derive(Clone, Copy, Default, PartialEq)]
+enum Shape {
+    #[default]
+    Empty,
+    Bytes,
+    Str,
+    Length(usize),
+    Slice,
+    Other,
+}
+
+impl KeyBytes {
+    pub(crate) fn of<T: Hash + ?Sized>(value: &T) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        KeyBytes::append(value, &mut bytes);
+        bytes
+    }
+
+    // Appends the key's bytes to `out`, so a batch of keys can share one
+    // buffer.
+    pub(crate) fn append<T: Hash + ?Sized>(value: &T, out: &mut Vec<u8>) {
+        let start = out.len();
+        let mut key = KeyBytes {
+            bytes: std::mem::take(out),
+            shape: Shape::Empty,
+        };
+        value.hash(&mut key);
+        *out = key.bytes;
+        match key.shape {
+            Shape::Str => {
+                out.pop();
+            }
+            Shape::Slice => {
+                out.drain(start..start + 8);
+            }
+            _ => {}
+        }
+    }
+
+    // Appends an integer's bytes, which rules out either raw shape.
+    fn push(&mut self, bytes: &[u8]) {
+        self.shape = Shape::Other;
+        self.bytes.extend_from_slice(bytes);
+    }
+}
+
+impl Hasher for KeyBytes {
+    fn finish(&self) -> u64 {
+        fasthash::murmur3::hash32(&self.bytes) as u64
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.shape = match self.shape {
+            Shape::Empty => Shape::Bytes,
+            Shape::Length(len) if len == bytes.len() => Shape::Slice,
+            _ => Shape::Other,
+        };
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        let shape = match self.shape {
+            Shape::Bytes if i == 0xff => Shape::Str,
+            _ => Shape::Other,
+        };
+        self.push(&[i]);
+        self.shape = shape;
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.push(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.push(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.push(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.push(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        let shape = match self.shape {
+            Shape::Empty => Shape::Length(i),
+            _ => Shape::Other,
+        };
+        self.write_u64(i as u64);
+        self.shape = shape;
+    }
+
+    fn write_i8(&mut self, i: i8) {
+        self.push(&[i as u8]);
+    }
+
+    fn write_i16(&mut self, i: i16) {
+        self.write_u16(i as u16);
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.write_u32(i as u32);
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i128(&mut self, i: i128) {
+        self.write_u128(i as u128);
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_u64(i as u64);
+    }
+}
+
+// Saved filters use this layout, all integers little endian:
+//
+//   offset  size  field
+//        0     4  magic, b"BLMF"
+//        4     2  format version, currently 1
+//        6     1  hash strategy, the HashStrategy discriminant
+//        7     1  reserved, zero
+//        8     8  number of hash functions
+//       16     8  number of bits m
+//       24     4  crc32 of bytes 0..24 followed by the payload
+//       28     4  reserved, zero
+//       32     -  payload: ceil(m / 64) u64 words. Bit i of the filter is bit
+//                 i % 64 of word i / 64, unused bits of the last word are zero.
+pub const MAGIC: [u8; 4] = *b"BLMF";
+pub const VERSION: u16 = 1;
+pub const HEADER_LEN: usize = 32;
+
+// Offset of the checksum, the header bytes before it are checksummed.
+const CHECKSUM_OFFSET: usize = 24;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Header {
+    pub(crate) strategy: HashStrategy,
+    pub(crate) hashes: usize,
+    pub(crate) bits: usize,
+    pub(crate) checksum: u32,
+}
+
+impl Header {
+    pub(crate) fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        bytes[6] = self.strategy as u8;
+        bytes[8..16].copy_from_slice(&(self.hashes as u64).to_le_bytes());
+        bytes[16..24].copy_from_slice(&(self.bits as u64).to_le_bytes());
+        bytes[24..28].copy_from_slice(&self.checksum.to_le_bytes());
+        bytes
+    }
+
+    pub(crate) fn decode(bytes: &[u8; HEADER_LEN]) -> Result<Header, DecodeError> {
+        if bytes[0..4] != MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        let strategy =
+            HashStrategy::from_u8(bytes[6]).ok_or(DecodeError::UnknownHashStrategy(bytes[6]))?;
+        if bytes[7] != 0 || bytes[28..32] != [0; 4] {
+            return Err(DecodeError::Corrupt("reserved header bytes are not zero"));
+        }
+        let hashes = u64::from_le_bytes(bytes[8..16].try_into().unwrap());
+        let bits = u64::from_le_bytes(bytes[16..24].try_into().unwrap());
+        if hashes == 0 || bits == 0 {
+            return Err(DecodeError::Corrupt("filter has no bits or no hashes"));
+        }
+        Ok(Header {
+            strategy,
+            hashes: usize::try_from(hashes)
+                .map_err(|_| DecodeError::Corrupt("hash count does not fit in usize"))?,
+            bits: usize::try_from(bits)
+                .map_err(|_| DecodeError::Corrupt("bit length does not fit in usize"))?,
+            checksum: u32::from_le_bytes(bytes[24..28].try_into().unwrap()),
+        })
+    }
+
+    // A crc32 that has already seen the checksummed part of the header.
+    pub(crate) fn crc(&self) -> crc32fast::Hasher {
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(&self.encode()[..CHECKSUM_OFFSET]);
+        crc
+    }
+
+    // Number of u64 words in the payload.
+    pub(crate) fn words(&self) -> usize {
+        self.bits.div_ceil(64)
+    }
+}
 
-pub struct BloomFilter {
-    bitvec: Vec<u8>,
+// Two filters can only be combined when they map every key to the same bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IncompatibleFilters {
+    BitLength(usize, usize),
+    HashCount(usize, usize),
+    HashStrategy(HashStrategy, HashStrategy),
+}
+
+impl fmt::Display for IncompatibleFilters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IncompatibleFilters::BitLength(a, b) => {
+                write!(f, "filters have different bit lengths: {} and {}", a, b)
+            }
+            IncompatibleFilters::HashCount(a, b) => {
+                write!(f, "filters have different hash counts: {} and {}", a, b)
+            }
+            IncompatibleFilters::HashStrategy(a, b) => {
+                write!(
+                    f,
+                    "filters use different hash strategies: {:?} and {:?}",
+                    a, b
+                )
+            }
+        }
+    }
+}
+
+impl Error for IncompatibleFilters {}
+
+// BloomFilter accepts any key implementing `Hash`. Keys that are already bytes
+// can skip the `Hash` step with `insert_bytes`/`get_bytes`. Either way the
+// bytes go through the filter's HashStrategy, murmur3 unless `with_strategy`
+// picked another one. The m bits are packed into u64 words, least significant
+// bit first, so the filter takes m / 8 bytes.
+pub struct BloomFilter<T: ?Sized> {
+    bitvec: BitVec<u64, Lsb0>,
     hashes: usize,
+    strategy: HashStrategy,
+    _key: PhantomData<fn(&T)>,
 }
 
-impl BloomFilter {
-    pub fn new(size: usize, fp_rate: f64) -> BloomFilter {
-        let m = num_bits(size, fp_rate);
-        let k = num_hashes(m, size);
-        BloomFilter {
-            bitvec: vec![0; m],
-            hashes: k,
+impl<T: ?Sized> BloomFilter<T> {
+    // Uses the default strategy, or DoubleHashing past the 2^32 bits murmur3
+    // can address.
+    pub fn new(size: usize, fp_rate: f64) -> BloomFilter<T> {
+        BloomFilterBuilder::new()
+            .expected_items(size)
+            .fp_rate(fp_rate)
+            .build()
+            .unwrap_or_else(|err| panic!("invalid bloom filter parameters: {}", err))
+    }
+
+    // Panics when size is 0 or fp_rate isn't strictly between 0 and 1. Use a
+    // BloomFilterBuilder to get an error instead.
+    pub fn with_strategy(size: usize, fp_rate: f64, strategy: HashStrategy) -> BloomFilter<T> {
+        BloomFilterBuilder::new()
+            .expected_items(size)
+            .fp_rate(fp_rate)
+            .strategy(strategy)
+            .build()
+            .unwrap_or_else(|err| panic!("invalid bloom filter parameters: {}", err))
+    }
+
+    pub fn insert_bytes(&mut self, value: &[u8]) {
+        let bitvec = &mut self.bitvec;
+        let m = bitvec.len();
+        self.strategy.for_each_index(value, self.hashes, m, |pos| {
+            bitvec.set(pos, true);
+            true
+        });
+    }
+
+    pub fn get_bytes(&self, value: &[u8]) -> bool {
+        self.strategy
+            .for_each_index(value, self.hashes, self.bitvec.len(), |pos| {
+                self.bitvec[pos]
+            })
+    }
+
+    // Hashes a batch of keys into `positions`, k per key, prefetching the
+    // word of every position as soon as it is known. By the time the batch is
+    // applied the loads have had the whole batch's hashing to complete, so
+    // up to BATCH * k cache misses are in flight at once instead of one.
+    fn hash_batch(&self, batch: &KeyBatch, positions: &mut Vec<usize>) {
+        let words = self.bitvec.as_raw_slice();
+        positions.clear();
+        for key in batch.keys() {
+            self.strategy
+                .for_each_index(key, self.hashes, self.bitvec.len(), |pos| {
+                    prefetch(&words[pos / 64]);
+                    positions.push(pos);
+                    true
+                });
         }
     }
 
-    pub fn insert(&mut self, value: &str) {
-        for i in 0..self.hashes {
-            let index = fasthash::murmur3::hash32_with_seed(value, i as u32)
-                % (self.bitvec.len() as u32 * 8);
-            let pos = index as usize;
-            self.bitvec[pos / 8] |= 1 << (pos % 8);
+    // Inserts every key, BATCH at a time. The result is the same as calling
+    // insert_bytes on each one.
+    pub fn insert_many_bytes<I, K>(&mut self, values: I)
+    where
+        I: IntoIterator<Item = K>,
+        K: AsRef<[u8]>,
+    {
+        self.insert_batches(values, |batch, key| batch.push(key.as_ref()));
+    }
+
+    // Feeds the values to `push` BATCH at a time and sets the bits of each
+    // batch once it is hashed.
+    fn insert_batches<I, F>(&mut self, values: I, mut push: F)
+    where
+        I: IntoIterator,
+        F: FnMut(&mut KeyBatch, I::Item),
+    {
+        let mut batch = KeyBatch::default();
+        let mut positions = Vec::with_capacity(BATCH * self.hashes);
+        let mut values = values.into_iter().peekable();
+        while values.peek().is_some() {
+            batch.clear();
+            for value in values.by_ref().take(BATCH) {
+                push(&mut batch, value);
+            }
+            self.hash_batch(&batch, &mut positions);
+            for &pos in &positions {
+                self.bitvec.set(pos, true);
+            }
         }
     }
 
-    pub fn get(&self, value: &str) -> bool {
-        for i in 0..self.hashes {
-            let index = fasthash::murmur3::hash32_with_seed(value, i as u32)
-                % (self.bitvec.len() as u32 * 8);
-            let pos = index as usize;
-            if (1 << (pos % 8)) & self.bitvec[pos / 8] == 0 {
-                return false;
+    // Looks up every key, BATCH at a time, appending one result per key to
+    // `out`, which can be a Vec<bool> or a BitVec.
+    pub fn contains_many_bytes_into<I, K, E>(&self, values: I, out: &mut E)
+    where
+        I: IntoIterator<Item = K>,
+        K: AsRef<[u8]>,
+        E: Extend<bool>,
+    {
+        self.contains_batches(values, out, |batch, key| batch.push(key.as_ref()));
+    }
+
+    // The lookup counterpart of insert_batches.
+    fn contains_batches<I, E, F>(&self, values: I, out: &mut E, mut push: F)
+    where
+        I: IntoIterator,
+        E: Extend<bool>,
+        F: FnMut(&mut KeyBatch, I::Item),
+    {
+        let mut batch = KeyBatch::default();
+        let mut positions = Vec::with_capacity(BATCH * self.hashes);
+        let mut values = values.into_iter().peekable();
+        while values.peek().is_some() {
+            batch.clear();
+            for value in values.by_ref().take(BATCH) {
+                push(&mut batch, value);
             }
+            self.hash_batch(&batch, &mut positions);
+            out.extend(
+                positions
+                    .chunks_exact(self.hashes)
+                    .map(|key| key.iter().all(|&pos| self.bitvec[pos])),
+            );
+        }
+    }
+
+    pub fn contains_many_bytes<I, K>(&self, values: I) -> Vec<bool>
+    where
+        I: IntoIterator<Item = K>,
+        K: AsRef<[u8]>,
+    {
+        let mut found = Vec::new();
+        self.contains_many_bytes_into(values, &mut found);
+        found
+    }
+
+    // Number of bits in the filter.
+    pub fn len(&self) -> usize {
+        self.bitvec.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bitvec.is_empty()
+    }
+
+    pub fn hashes(&self) -> usize {
+        self.hashes
+    }
+
+    pub fn strategy(&self) -> HashStrategy {
+        self.strategy
+    }
+
+    pub fn check_compatible(&self, other: &BloomFilter<T>) -> Result<(), IncompatibleFilters> {
+        if self.bitvec.len() != other.bitvec.len() {
+            return Err(IncompatibleFilters::BitLength(
+                self.bitvec.len(),
+                other.bitvec.len(),
+            ));
+        }
+        if self.hashes != other.hashes {
+            return Err(IncompatibleFilters::HashCount(self.hashes, other.hashes));
+        }
+        if self.strategy != other.strategy {
+            return Err(IncompatibleFilters::HashStrategy(
+                self.strategy,
+                other.strategy,
+            ));
+        }
+        Ok(())
+    }
+
+    // Adds every key of `other` to this filter. The result is exactly the
+    // filter that inserting both key sets into one filter would have built.
+    pub fn union_in_place(&mut self, other: &BloomFilter<T>) -> Result<(), IncompatibleFilters> {
+        self.check_compatible(other)?;
+        let words = self.bitvec.as_raw_mut_slice();
+        for (word, other) in words.iter_mut().zip(other.bitvec.as_raw_slice()) {
+            *word |= other;
+        }
+        Ok(())
+    }
+
+    pub fn union(&self, other: &BloomFilter<T>) -> Result<BloomFilter<T>, IncompatibleFilters> {
+        let mut union = self.clone();
+        union.union_in_place(other)?;
+        Ok(union)
+    }
+
+    // Keeps only the bits set in both filters. Every key inserted into both is
+    // still found, but the false positive rate can be higher than that of a
+    // filter built from the common keys alone.
+    pub fn intersection_in_place(
+        &mut self,
+        other: &BloomFilter<T>,
+    ) -> Result<(), IncompatibleFilters> {
+        self.check_compatible(other)?;
+        let words = self.bitvec.as_raw_mut_slice();
+        for (word, other) in words.iter_mut().zip(other.bitvec.as_raw_slice()) {
+            *word &= other;
+        }
+        Ok(())
+    }
+
+    pub fn intersection(
+        &self,
+        other: &BloomFilter<T>,
+    ) -> Result<BloomFilter<T>, IncompatibleFilters> {
+        let mut intersection = self.clone();
+        intersection.intersection_in_place(other)?;
+        Ok(intersection)
+    }
+
+    // Number of bits set to one.
+    pub fn count_ones(&self) -> usize {
+        self.bitvec.count_ones()
+    }
+
+    // Fraction of the bits set to one, 0.5 for a filter filled to the
+    // capacity it was sized for.
+    pub fn fill_ratio(&self) -> f64 {
+        self.count_ones() as f64 / self.bitvec.len() as f64
+    }
+
+    // Swamidass-Baldi estimate of how many distinct keys were inserted.
+    pub fn estimate_len(&self) -> f64 {
+        self.estimate_items(self.count_ones())
+    }
+
+    // Swamidass-Baldi estimate of how many distinct keys set `ones` of the
+    // filter's bits: n = -(m / k) * ln(1 - ones / m). A saturated filter is
+    // treated as having one bit left clear so the estimate stays finite.
+    fn estimate_items(&self, ones: usize) -> f64 {
+        let m = self.bitvec.len() as f64;
+        let ones = (ones as f64).min(m - 1.0);
+        -(m / self.hashes as f64) * (1.0 - ones / m).ln()
+    }
+
+    // Probability that a key never inserted is reported present, given the
+    // bits set so far: every one of its k bits has to be set.
+    pub fn current_fp_rate(&self) -> f64 {
+        self.fill_ratio().powf(self.hashes as f64)
+    }
+
+    // False positive rate once `items` distinct keys are in the filter.
+    pub fn expected_fp_rate(&self, items: usize) -> f64 {
+        fp_rate(self.bitvec.len(), self.hashes, items)
+    }
+
+    // How many keys the filter holds before its false positive rate rises
+    // above `fp_rate`, the inverse of the sizing done by `new`.
+    pub fn capacity(&self, fp_rate: f64) -> usize {
+        num_items(self.bitvec.len(), fp_rate)
+    }
+
+    // Whether the filter holds more keys than it can at `fp_rate`.
+    pub fn is_saturated(&self, fp_rate: f64) -> bool {
+        self.current_fp_rate() > fp_rate
+    }
+
+    // Estimated number of keys inserted into both filters, from the sizes of
+    // the two sets and of their union.
+    pub fn estimate_overlap(&self, other: &BloomFilter<T>) -> Result<f64, IncompatibleFilters> {
+        self.check_compatible(other)?;
+        let union_ones: usize = self
+            .bitvec
+            .as_raw_slice()
+            .iter()
+            .zip(other.bitvec.as_raw_slice())
+            .map(|(a, b)| (a | b).count_ones() as usize)
+            .sum();
+        let overlap = self.estimate_len() + other.estimate_len() - self.estimate_items(union_ones);
+        Ok(overlap.max(0.0))
+    }
+
+    // Estimated Jaccard similarity |A & B| / |A | B| of the two key sets.
+    // Two empty filters are considered identical.
+    pub fn estimate_jaccard(&self, other: &BloomFilter<T>) -> Result<f64, IncompatibleFilters> {
+        let overlap = self.estimate_overlap(other)?;
+        let union = self.union(other)?;
+        let union_items = union.estimate_len();
+        if union_items == 0.0 {
+            return Ok(1.0);
+        }
+        Ok((overlap / union_items).clamp(0.0, 1.0))
+    }
+
+    fn header(&self) -> Header {
+        let mut header = Header {
+            strategy: self.strategy,
+            hashes: self.hashes,
+            bits: self.bitvec.len(),
+            checksum: 0,
+        };
+        let mut crc = header.crc();
+        checksum_words(&mut crc, self.bitvec.as_raw_slice());
+        header.checksum = crc.finalize();
+        header
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.bitvec.as_raw_slice().len() * 8);
+        self.write_to(&mut bytes)
+            .expect("writing to a Vec never fails");
+        bytes
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.header().encode())?;
+        write_words(&mut writer, self.bitvec.as_raw_slice())
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<BloomFilter<T>, DecodeError> {
+        from_slice(bytes, |reader| BloomFilter::read_from(reader))
+    }
+
+    // Reads exactly one filter, leaving anything after it in the reader.
+    pub fn read_from<R: Read>(mut reader: R) -> Result<BloomFilter<T>, DecodeError> {
+        let header = Header::decode(&read_array(&mut reader)?)?;
+        let mut crc = header.crc();
+        let words = read_words(&mut reader, header.words(), &mut crc)?;
+        let found = crc.finalize();
+        if found != header.checksum {
+            return Err(DecodeError::ChecksumMismatch {
+                expected: header.checksum,
+                found,
+            });
+        }
+        if words.view_bits::<Lsb0>()[header.bits..].any() {
+            return Err(DecodeError::Corrupt("bits set past the end of the filter"));
+        }
+        Ok(BloomFilter::from_words(
+            words,
+            header.bits,
+            header.hashes,
+            header.strategy,
+        ))
+    }
+
+    // Builds a filter around `words`, bit i being bit i % 64 of word i / 64.
+    // The bits past `bits` in the last word must be zero.
+    pub(crate) fn from_words(
+        words: Vec<u64>,
+        bits: usize,
+        hashes: usize,
+        strategy: HashStrategy,
+    ) -> BloomFilter<T> {
+        let mut bitvec = BitVec::from_vec(words);
+        bitvec.truncate(bits);
+        BloomFilter {
+            bitvec,
+            hashes,
+            strategy,
+            _key: PhantomData,
+        }
+    }
+
+    pub(crate) fn into_words(self) -> Vec<u64> {
+        self.bitvec.into_vec()
+    }
+}
+
+// Derived Clone would require T: Clone, which unsized keys like str can't be.
+impl<T: ?Sized> Clone for BloomFilter<T> {
+    fn clone(&self) -> Self {
+        BloomFilter {
+            bitvec: self.bitvec.clone(),
+            hashes: self.hashes,
+            strategy: self.strategy,
+            _key: PhantomData,
         }
+    }
+}
+
+impl<T: Hash + ?Sized> BloomFilter<T> {
+    pub fn insert(&mut self, value: &T) {
+        self.insert_bytes(&KeyBytes::of(value));
+    }
+
+    pub fn get(&self, value: &T) -> bool {
+        self.get_bytes(&KeyBytes::of(value))
+    }
+
+    pub fn insert_many<'a, I>(&mut self, values: I)
+    where
+        I: IntoIterator<Item = &'a T>,
+        T: 'a,
+    {
+        self.insert_batches(values, KeyBatch::push_key);
+    }
 
-        return true;
+    pub fn contains_many_into<'a, I, E>(&self, values: I, out: &mut E)
+    where
+        I: IntoIterator<Item = &'a T>,
+        T: 'a,
+        E: Extend<bool>,
+    {
+        self.contains_batches(values, out, KeyBatch::push_key);
+    }
+
+    pub fn contains_many<'a, I>(&self, values: I) -> Vec<bool>
+    where
+        I: IntoIterator<Item = &'a T>,
+        T: 'a,
+    {
+        let mut found = Vec::new();
+        self.contains_many_into(values, &mut found);
+        found
     }
 }
 
@@ -64,4 +764,468 @@ mod tests {
         assert!(!filter.get("bazz"));
         assert!(filter.get("test"));
     }
+
+    #[test]
+    fn test_generic_keys() {
+        let mut filter = BloomFilter::<(u64, &str)>::new(10, 0.001);
+        filter.insert(&(1, "one"));
+        filter.insert(&(2, "two"));
+        assert!(filter.get(&(1, "one")));
+        assert!(filter.get(&(2, "two")));
+        assert!(!filter.get(&(1, "two")));
+    }
+
+    #[test]
+    fn test_insert_bytes() {
+        let mut filter = BloomFilter::<[u8]>::new(10, 0.001);
+        filter.insert_bytes(b"test");
+        assert!(filter.get_bytes(b"test"));
+        assert!(!filter.get_bytes(b"bar"));
+        filter.insert(b"foo".as_slice());
+        assert!(filter.get(b"foo".as_slice()));
+    }
+
+    // The bits the original &str-only filter set: seeded murmur3 of the raw
+    // bytes, one seed per hash function.
+    fn baseline_bits(key: &[u8], hashes: usize, m: usize) -> Vec<usize> {
+        let mut bits: Vec<usize> = (0..hashes)
+            .map(|i| (fasthash::murmur3::hash32_with_seed(key, i as u32) % m as u32) as usize)
+            .collect();
+        bits.sort_unstable();
+        bits.dedup();
+        bits
+    }
+
+    fn set_bits<T: ?Sized>(filter: &BloomFilter<T>) -> Vec<usize> {
+        filter.bitvec.iter_ones().collect()
+    }
+
+    #[test]
+    fn test_key_batch() {
+        let mut batch = KeyBatch::default();
+        batch.push_key("test");
+        batch.push_key(&[1u8, 2, 3][..]);
+        batch.push(b"raw");
+        batch.push_key(&("ab", 7u64));
+        batch.push_key("");
+        let expected = [
+            KeyBytes::of("test"),
+            vec![1, 2, 3],
+            b"raw".to_vec(),
+            KeyBytes::of(&("ab", 7u64)),
+            Vec::new(),
+        ];
+        assert!(batch.keys().eq(expected.iter().map(Vec::as_slice)));
+        batch.clear();
+        assert_eq!(batch.keys().count(), 0);
+
+        let keys = ["foo", "", "bar", "foo"];
+        let mut batched = BloomFilter::<str>::new(100, 0.01);
+        let mut scalar = BloomFilter::<str>::new(100, 0.01);
+        batched.insert_many(keys);
+        for key in keys {
+            scalar.insert(key);
+        }
+        assert_eq!(batched.bitvec, scalar.bitvec);
+        assert_eq!(batched.contains_many(["foo", "baz"]), [true, false]);
+    }
+
+    #[test]
+    fn test_byte_like_keys_match_baseline() {
+        for key in ["test", "", "hello world", "\u{e9}t\u{e9}"] {
+            let expected = baseline_bits(key.as_bytes(), 7, 9586);
+
+            let mut by_str = BloomFilter::<str>::new(1000, 0.01);
+            assert_eq!((by_str.hashes(), by_str.len()), (7, 9586));
+            by_str.insert(key);
+            assert_eq!(set_bits(&by_str), expected, "str {:?}", key);
+
+            let mut by_string = BloomFilter::<String>::new(1000, 0.01);
+            by_string.insert(&key.to_string());
+            assert_eq!(set_bits(&by_string), expected, "String {:?}", key);
+
+            let mut by_slice = BloomFilter::<[u8]>::new(1000, 0.01);
+            by_slice.insert(key.as_bytes());
+            assert_eq!(set_bits(&by_slice), expected, "[u8] {:?}", key);
+
+            let mut by_vec = BloomFilter::<Vec<u8>>::new(1000, 0.01);
+            by_vec.insert(&key.as_bytes().to_vec());
+            assert_eq!(set_bits(&by_vec), expected, "Vec<u8> {:?}", key);
+        }
+        // Other keys keep their Hash encoding: the terminator and length
+        // prefix still keep composite keys apart.
+        assert_ne!(KeyBytes::of(&("ab", "c")), KeyBytes::of(&("a", "bc")));
+        assert_ne!(KeyBytes::of(&[[1u8], [2]]), KeyBytes::of(&[[1u8, 2]]));
+        assert_eq!(KeyBytes::of(&255u8), [255]);
+        assert_eq!(KeyBytes::of(&7u64), 7u64.to_le_bytes());
+    }
+
+    #[test]
+    fn test_strategies() {
+        for strategy in [
+            HashStrategy::Murmur3,
+            HashStrategy::XxHash,
+            HashStrategy::SipHash,
+            HashStrategy::DoubleHashing,
+        ] {
+            let mut filter = BloomFilter::with_strategy(10, 0.001, strategy);
+            filter.insert("test");
+            assert_eq!(filter.strategy(), strategy);
+            assert!(filter.get("test"));
+            assert!(!filter.get("bar"));
+        }
+    }
+
+    #[test]
+    fn test_insert_many_matches_insert() {
+        let keys: Vec<u64> = (0..1000).collect();
+        for strategy in [
+            HashStrategy::Murmur3,
+            HashStrategy::XxHash,
+            HashStrategy::SipHash,
+            HashStrategy::DoubleHashing,
+        ] {
+            let mut batched = BloomFilter::with_strategy(1000, 0.01, strategy);
+            batched.insert_many(&keys);
+            let mut scalar = BloomFilter::with_strategy(1000, 0.01, strategy);
+            for key in &keys {
+                scalar.insert(key);
+            }
+            assert_eq!(batched.to_bytes(), scalar.to_bytes());
+        }
+    }
+
+    #[test]
+    fn test_contains_many_matches_get() {
+        let mut filter = BloomFilter::<u64>::new(500, 0.05);
+        filter.insert_many(&(0..500).step_by(2).collect::<Vec<_>>());
+        // Not a multiple of the batch size, to cover the short last batch.
+        let keys: Vec<u64> = (0..1003).collect();
+        let expected: Vec<bool> = keys.iter().map(|key| filter.get(key)).collect();
+        assert_eq!(filter.contains_many(&keys), expected);
+        assert!(expected.iter().step_by(2).take(250).all(|&found| found));
+
+        let mut bits = BitVec::<u64, Lsb0>::new();
+        filter.contains_many_into(&keys, &mut bits);
+        assert_eq!(bits.iter().by_vals().collect::<Vec<_>>(), expected);
+
+        assert!(filter.contains_many(&[]).is_empty());
+        let mut bytes = BloomFilter::<[u8]>::new(10, 0.01);
+        bytes.insert_many_bytes(["foo", "bar"]);
+        assert_eq!(
+            bytes.contains_many_bytes(["foo", "baz", "bar"]),
+            [true, false, true]
+        );
+    }
+
+    fn filled(strategy: HashStrategy) -> BloomFilter<u64> {
+        let mut filter = BloomFilter::with_strategy(1000, 0.01, strategy);
+        for i in 0..1000 {
+            filter.insert(&i);
+        }
+        filter
+    }
+
+    #[test]
+    fn test_serialization_round_trip() {
+        for strategy in [HashStrategy::Murmur3, HashStrategy::DoubleHashing] {
+            let filter = filled(strategy);
+            let bytes = filter.to_bytes();
+            assert_eq!(bytes.len(), HEADER_LEN + filter.len().div_ceil(64) * 8);
+            assert_eq!(bytes[0..4], MAGIC);
+
+            let loaded = BloomFilter::<u64>::from_bytes(&bytes).unwrap();
+            assert_eq!(loaded.len(), filter.len());
+            assert_eq!(loaded.hashes(), filter.hashes());
+            assert_eq!(loaded.strategy(), strategy);
+            assert_eq!(loaded.bitvec, filter.bitvec);
+            for i in 0..1000 {
+                assert!(loaded.get(&i));
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_to_read_from() {
+        let filter = filled(HashStrategy::XxHash);
+        let mut buf = Vec::new();
+        filter.write_to(&mut buf).unwrap();
+        filter.write_to(&mut buf).unwrap();
+        // read_from stops at the end of each filter.
+        let mut reader = buf.as_slice();
+        let first = BloomFilter::<u64>::read_from(&mut reader).unwrap();
+        let second = BloomFilter::<u64>::read_from(&mut reader).unwrap();
+        assert!(reader.is_empty());
+        assert_eq!(first.bitvec, filter.bitvec);
+        assert_eq!(second.bitvec, filter.bitvec);
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_bad_input() {
+        let bytes = filled(HashStrategy::Murmur3).to_bytes();
+
+        for len in [0, 10, HEADER_LEN, bytes.len() - 1] {
+            assert!(matches!(
+                BloomFilter::<u64>::from_bytes(&bytes[..len]),
+                Err(DecodeError::Truncated)
+            ));
+        }
+
+        let mut extra = bytes.clone();
+        extra.push(0);
+        assert!(matches!(
+            BloomFilter::<u64>::from_bytes(&extra),
+            Err(DecodeError::Corrupt(_))
+        ));
+
+        let mut corrupt = bytes.clone();
+        corrupt[0] = b'X';
+        assert!(matches!(
+            BloomFilter::<u64>::from_bytes(&corrupt),
+            Err(DecodeError::BadMagic)
+        ));
+
+        let mut corrupt = bytes.clone();
+        corrupt[4] = 2;
+        assert!(matches!(
+            BloomFilter::<u64>::from_bytes(&corrupt),
+            Err(DecodeError::UnsupportedVersion(2))
+        ));
+
+        let mut corrupt = bytes.clone();
+        corrupt[6] = 200;
+        assert!(matches!(
+            BloomFilter::<u64>::from_bytes(&corrupt),
+            Err(DecodeError::UnknownHashStrategy(200))
+        ));
+
+        let mut corrupt = bytes.clone();
+        corrupt[HEADER_LEN + 3] ^= 0x10;
+        assert!(matches!(
+            BloomFilter::<u64>::from_bytes(&corrupt),
+            Err(DecodeError::ChecksumMismatch { .. })
+        ));
+
+        let mut corrupt = bytes.clone();
+        corrupt[8] ^= 1;
+        assert!(matches!(
+            BloomFilter::<u64>::from_bytes(&corrupt),
+            Err(DecodeError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_union() {
+        let mut a = BloomFilter::<u64>::new(1000, 0.01);
+        let mut b = BloomFilter::<u64>::new(1000, 0.01);
+        let mut both = BloomFilter::<u64>::new(1000, 0.01);
+        for i in 0..500 {
+            a.insert(&i);
+            both.insert(&i);
+        }
+        for i in 500..1000 {
+            b.insert(&i);
+            both.insert(&i);
+        }
+        let union = a.union(&b).unwrap();
+        assert_eq!(union.bitvec, both.bitvec);
+        a.union_in_place(&b).unwrap();
+        assert_eq!(a.bitvec, both.bitvec);
+        for i in 0..1000 {
+            assert!(a.get(&i));
+        }
+    }
+
+    #[test]
+    fn test_intersection() {
+        let mut a = BloomFilter::<u64>::new(1000, 0.01);
+        let mut b = BloomFilter::<u64>::new(1000, 0.01);
+        for i in 0..600 {
+            a.insert(&i);
+        }
+        for i in 400..1000 {
+            b.insert(&i);
+        }
+        let intersection = a.intersection(&b).unwrap();
+        for i in 400..600 {
+            assert!(intersection.get(&i));
+        }
+        let false_positives = (0..400)
+            .chain(600..1000)
+            .filter(|i| intersection.get(i))
+            .count();
+        assert!(false_positives < 100);
+    }
+
+    #[test]
+    fn test_incompatible() {
+        let a = BloomFilter::<u64>::new(1000, 0.01);
+        let b = BloomFilter::<u64>::new(2000, 0.01);
+        assert_eq!(
+            a.union(&b).err(),
+            Some(IncompatibleFilters::BitLength(a.len(), b.len()))
+        );
+        let mut c = a.clone();
+        c.hashes += 1;
+        assert_eq!(
+            a.intersection(&c).err(),
+            Some(IncompatibleFilters::HashCount(a.hashes, c.hashes))
+        );
+        let d = BloomFilter::<u64>::with_strategy(1000, 0.01, HashStrategy::SipHash);
+        let mut e = a.clone();
+        assert_eq!(
+            e.union_in_place(&d),
+            Err(IncompatibleFilters::HashStrategy(
+                HashStrategy::Murmur3,
+                HashStrategy::SipHash
+            ))
+        );
+        assert!(a.estimate_jaccard(&d).is_err());
+    }
+
+    #[test]
+    fn test_estimate_overlap() {
+        let mut a = BloomFilter::<u64>::new(10_000, 0.01);
+        let mut b = BloomFilter::<u64>::new(10_000, 0.01);
+        for i in 0..6000 {
+            a.insert(&i);
+        }
+        for i in 3000..9000 {
+            b.insert(&i);
+        }
+        // 3000 shared keys out of 9000 distinct ones.
+        let overlap = a.estimate_overlap(&b).unwrap();
+        assert!((overlap - 3000.0).abs() < 150.0, "overlap {}", overlap);
+        let jaccard = a.estimate_jaccard(&b).unwrap();
+        assert!((jaccard - 1.0 / 3.0).abs() < 0.02, "jaccard {}", jaccard);
+
+        assert!((a.estimate_jaccard(&a).unwrap() - 1.0).abs() < 1e-9);
+        let empty = BloomFilter::<u64>::new(10_000, 0.01);
+        assert_eq!(empty.estimate_jaccard(&empty).unwrap(), 1.0);
+        assert_eq!(a.estimate_jaccard(&empty).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn test_sizing_round_trip() {
+        for (size, rate) in [(1000, 0.01), (10_000, 0.001), (123, 0.2)] {
+            let m = num_bits(size, rate);
+            let k = num_hashes(m, size);
+            let items = num_items(m, rate);
+            assert!(items >= size && items <= size + 1, "{} {}", size, items);
+            // Rounding k up moves the rate slightly off the optimum.
+            assert!((fp_rate(m, k, size) - rate).abs() < rate * 0.1);
+        }
+    }
+
+    #[test]
+    fn test_introspection() {
+        let mut filter = BloomFilter::<u64>::new(10_000, 0.01);
+        assert_eq!(filter.count_ones(), 0);
+        assert_eq!(filter.fill_ratio(), 0.0);
+        assert_eq!(filter.estimate_len(), 0.0);
+        assert_eq!(filter.current_fp_rate(), 0.0);
+        assert!(filter.capacity(0.01) >= 10_000);
+
+        for i in 0..10_000 {
+            filter.insert(&i);
+        }
+        let estimate = filter.estimate_len();
+        assert!((estimate - 10_000.0).abs() < 300.0, "estimate {}", estimate);
+        assert!((filter.fill_ratio() - 0.5).abs() < 0.02);
+        assert!((filter.current_fp_rate() - 0.01).abs() < 0.002);
+        assert!((filter.expected_fp_rate(10_000) - 0.01).abs() < 0.001);
+        assert!(!filter.is_saturated(0.02));
+
+        for i in 10_000..30_000 {
+            filter.insert(&i);
+        }
+        assert!(filter.is_saturated(0.02));
+        assert!(filter.current_fp_rate() > filter.expected_fp_rate(10_000));
+        let estimate = filter.estimate_len();
+        assert!(
+            (estimate - 30_000.0).abs() < 1500.0,
+            "estimate {}",
+            estimate
+        );
+    }
+
+    #[test]
+    fn test_allocates_exactly_m_bits() {
+        let filter = BloomFilter::<str>::new(1000, 0.01);
+        let m = num_bits(1000, 0.01);
+        assert_eq!(filter.len(), m);
+        assert_eq!(filter.bitvec.as_raw_slice().len(), m.div_ceil(64));
+    }
+
+    // Inserts `size` keys and checks `probes` keys that were never inserted.
+    fn empirical_fp_rate(size: usize, fp_rate: f64, strategy: HashStrategy, probes: usize) -> f64 {
+        let mut filter = BloomFilter::<u64>::with_strategy(size, fp_rate, strategy);
+        for i in 0..size as u64 {
+            filter.insert(&i);
+        }
+        for i in 0..size as u64 {
+            assert!(filter.get(&i));
+        }
+        let start = size as u64;
+        let end = start + probes as u64;
+        let false_positives = (start..end).filter(|i| filter.get(i)).count();
+        false_positives as f64 / probes as f64
+    }
+
+    fn assert_fp_rate_close(size: usize, fp_rate: f64) {
+        assert_strategy_fp_rate_close(size, fp_rate, HashStrategy::default());
+    }
+
+    fn assert_strategy_fp_rate_close(size: usize, fp_rate: f64, strategy: HashStrategy) {
+        let measured = empirical_fp_rate(size, fp_rate, strategy, 200_000);
+        // The measured rate should sit within sampling noise of the
+        // configured one, neither well above it nor well below it.
+        assert!(
+            measured <= fp_rate * 1.25,
+            "{:?} size {} fp_rate {}: measured {}",
+            strategy,
+            size,
+            fp_rate,
+            measured
+        );
+        assert!(
+            measured >= fp_rate * 0.4,
+            "{:?} size {} fp_rate {}: measured {}",
+            strategy,
+            size,
+            fp_rate,
+            measured
+        );
+    }
+
+    #[test]
+    fn test_fp_rate_one_percent() {
+        assert_fp_rate_close(10_000, 0.01);
+    }
+
+    #[test]
+    fn test_fp_rate_five_percent() {
+        assert_fp_rate_close(5_000, 0.05);
+    }
+
+    #[test]
+    fn test_fp_rate_tenth_of_a_percent() {
+        assert_fp_rate_close(20_000, 0.001);
+    }
+
+    #[test]
+    fn test_fp_rate_small_filter() {
+        assert_fp_rate_close(100, 0.1);
+    }
+
+    #[test]
+    fn test_fp_rate_other_strategies() {
+        for strategy in [
+            HashStrategy::XxHash,
+            HashStrategy::SipHash,
+            HashStrategy::DoubleHashing,
+        ] {
+            assert_strategy_fp_rate_close(10_000, 0.01, strategy);
+        }
+    }
 }
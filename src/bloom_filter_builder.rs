@@ -0,0 +1,483 @@
+use crate::bloom_filter::{fp_rate, num_bits, num_hashes, BloomFilter};
+use crate::bloom_hasher::HashStrategy;
+use std::error::Error;
+use std::fmt;
+
+// The most bits a filter can have: bitvec addresses bits with a usize and
+// keeps three of its bits for the offset within a byte.
+const MAX_BITS: usize = usize::MAX >> 3;
+
+// Most hash functions a filter may use. Even a 1e-30 false positive rate
+// only needs 100; past that lookups just get slower.
+pub const MAX_HASHES: usize = 128;
+
+// Murmur3 positions are 32 bit hashes mod m, so bits past 2^32 are never set.
+const MAX_MURMUR3_BITS: u64 = 1 << 32;
+
+// Why a set of BloomFilter parameters was rejected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BloomFilterError {
+    // The false positive rate has to be strictly between 0 and 1.
+    InvalidFpRate(f64),
+    ZeroItems,
+    ZeroBits,
+    ZeroHashes,
+    // More hash functions than MAX_HASHES were asked for.
+    TooManyHashes(usize),
+    ZeroMemoryBudget,
+    // Not enough was given to size the filter; names what is missing.
+    MissingParameter(&'static str),
+    // Two parameters that each fix the same thing were both given.
+    ConflictingParameters(&'static str),
+    // The filter needs more bytes than the memory budget allows.
+    ExceedsMemoryBudget { required: usize, budget: usize },
+    // The filter would need more bits than can be addressed.
+    TooLarge,
+}
+
+impl fmt::Display for BloomFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BloomFilterError::InvalidFpRate(rate) => write!(
+                f,
+                "false positive rate must be between 0 and 1 exclusive, got {}",
+                rate
+            ),
+            BloomFilterError::ZeroItems => write!(f, "expected items must be positive"),
+            BloomFilterError::ZeroBits => write!(f, "bit count must be positive"),
+            BloomFilterError::ZeroHashes => write!(f, "hash count must be positive"),
+            BloomFilterError::TooManyHashes(hashes) => write!(
+                f,
+                "hash count must be at most {}, got {}",
+                MAX_HASHES, hashes
+            ),
+            BloomFilterError::ZeroMemoryBudget => write!(f, "memory budget must be positive"),
+            BloomFilterError::MissingParameter(what) => write!(f, "missing {}", what),
+            BloomFilterError::ConflictingParameters(what) => {
+                write!(f, "conflicting parameters: {}", what)
+            }
+            BloomFilterError::ExceedsMemoryBudget { required, budget } => write!(
+                f,
+                "filter needs {} bytes but the memory budget is {}",
+                required, budget
+            ),
+            BloomFilterError::TooLarge => write!(f, "filter is too large to allocate"),
+        }
+    }
+}
+
+impl Error for BloomFilterError {}
+
+// The sizes a builder settled on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BloomFilterParams {
+    pub bits: usize,
+    pub hashes: usize,
+    pub strategy: HashStrategy,
+    // The false positive rate once the expected items are in. Rounding bits
+    // and hashes to whole numbers moves it slightly off the requested rate.
+    // None when the builder wasn't told how many items to expect.
+    pub fp_rate: Option<f64>,
+}
+
+// BloomFilterBuilder sizes a BloomFilter from any of:
+//   - expected items and a false positive rate, like BloomFilter::new,
+//   - explicit bits, with explicit hashes or expected items to derive them,
+//   - expected items and a memory budget, using the whole budget.
+// A memory budget given alongside the others caps the size instead. Every
+// parameter is checked and bad combinations come back as a BloomFilterError.
+// Without an explicit strategy the default one is used, except for filters
+// past 2^32 bits, which murmur3 can't address, where it is DoubleHashing.
+#[derive(Clone, Debug, Default)]
+pub struct BloomFilterBuilder {
+    expected_items: Option<usize>,
+    fp_rate: Option<f64>,
+    bits: Option<usize>,
+    hashes: Option<usize>,
+    memory_budget: Option<usize>,
+    strategy: Option<HashStrategy>,
+}
+
+impl BloomFilterBuilder {
+    pub fn new() -> BloomFilterBuilder {
+        BloomFilterBuilder::default()
+    }
+
+    pub fn expected_items(mut self, items: usize) -> BloomFilterBuilder {
+        self.expected_items = Some(items);
+        self
+    }
+
+    pub fn fp_rate(mut self, fp_rate: f64) -> BloomFilterBuilder {
+        self.fp_rate = Some(fp_rate);
+        self
+    }
+
+    pub fn bits(mut self, bits: usize) -> BloomFilterBuilder {
+        self.bits = Some(bits);
+        self
+    }
+
+    pub fn hashes(mut self, hashes: usize) -> BloomFilterBuilder {
+        self.hashes = Some(hashes);
+        self
+    }
+
+    // Largest size of the bit array in bytes.
+    pub fn memory_budget(mut self, bytes: usize) -> BloomFilterBuilder {
+        self.memory_budget = Some(bytes);
+        self
+    }
+
+    pub fn strategy(mut self, strategy: HashStrategy) -> BloomFilterBuilder {
+        self.strategy = Some(strategy);
+        self
+    }
+
+    fn check(&self) -> Result<(), BloomFilterError> {
+        if let Some(rate) = self.fp_rate {
+            // Written so NaN fails too.
+            if !(rate > 0.0 && rate < 1.0) {
+                return Err(BloomFilterError::InvalidFpRate(rate));
+            }
+        }
+        match (
+            self.expected_items,
+            self.bits,
+            self.hashes,
+            self.memory_budget,
+        ) {
+            (Some(0), _, _, _) => Err(BloomFilterError::ZeroItems),
+            (_, Some(0), _, _) => Err(BloomFilterError::ZeroBits),
+            (_, _, Some(0), _) => Err(BloomFilterError::ZeroHashes),
+            (_, _, Some(hashes), _) if hashes > MAX_HASHES => {
+                Err(BloomFilterError::TooManyHashes(hashes))
+            }
+            (_, _, _, Some(0)) => Err(BloomFilterError::ZeroMemoryBudget),
+            _ => Ok(()),
+        }
+    }
+
+    fn resolve_bits(&self) -> Result<usize, BloomFilterError> {
+        // Whole u64 words, which is how the bits are allocated.
+        let budget_bits = self
+            .memory_budget
+            .map(|bytes| (bytes / 8).saturating_mul(64).min(MAX_BITS));
+        let bits = match (self.bits, self.expected_items, self.fp_rate) {
+            (Some(_), Some(_), Some(_)) => {
+                return Err(BloomFilterError::ConflictingParameters(
+                    "bits and fp_rate with expected_items both fix the size",
+                ))
+            }
+            // Without expected items the rate can't pick the hashes either,
+            // so it would be silently ignored.
+            (Some(_), None, Some(_)) => {
+                return Err(BloomFilterError::ConflictingParameters(
+                    "fp_rate has no effect with bits and no expected_items",
+                ))
+            }
+            (Some(bits), _, None) => bits,
+            // Float to int casts saturate, so an oversized result is caught by
+            // the MAX_BITS check below.
+            (None, Some(items), Some(rate)) => num_bits(items, rate),
+            (None, Some(_), None) => budget_bits.ok_or(BloomFilterError::MissingParameter(
+                "fp_rate or memory_budget to go with expected_items",
+            ))?,
+            (None, None, _) => {
+                return Err(BloomFilterError::MissingParameter("expected_items or bits"))
+            }
+        };
+        if bits > MAX_BITS {
+            return Err(BloomFilterError::TooLarge);
+        }
+        if let Some(budget) = self.memory_budget {
+            let required = bits.div_ceil(64).max(1) * 8;
+            if required > budget {
+                return Err(BloomFilterError::ExceedsMemoryBudget { required, budget });
+            }
+        }
+        Ok(bits)
+    }
+
+    // Works out the filter's sizes without allocating it.
+    pub fn params(&self) -> Result<BloomFilterParams, BloomFilterError> {
+        self.check()?;
+        let bits = self.resolve_bits()?;
+        let hashes = match (self.hashes, self.expected_items) {
+            (Some(hashes), _) => hashes,
+            // Far more bits than items would call for millions of hashes,
+            // where MAX_HASHES already gives a negligible rate.
+            (None, Some(items)) => num_hashes(bits, items).clamp(1, MAX_HASHES),
+            (None, None) => {
+                return Err(BloomFilterError::MissingParameter(
+                    "hashes or expected_items to go with bits",
+                ))
+            }
+        };
+        let wide = bits as u64 > MAX_MURMUR3_BITS;
+        let strategy = match self.strategy {
+            Some(HashStrategy::Murmur3) if wide => {
+                return Err(BloomFilterError::ConflictingParameters(
+                    "murmur3 only reaches the first 2^32 bits",
+                ))
+            }
+            Some(strategy) => strategy,
+            None if wide => HashStrategy::DoubleHashing,
+            None => HashStrategy::default(),
+        };
+        Ok(BloomFilterParams {
+            bits,
+            hashes,
+            strategy,
+            fp_rate: self
+                .expected_items
+                .map(|items| fp_rate(bits, hashes, items)),
+        })
+    }
+
+    pub fn build<T: ?Sized>(&self) -> Result<BloomFilter<T>, BloomFilterError> {
+        let params = self.params()?;
+        Ok(BloomFilter::from_words(
+            vec![0; params.bits.div_ceil(64)],
+            params.bits,
+            params.hashes,
+            params.strategy,
+        ))
+    }
+}
+
+// The params for `size` items at `fp_rate`, for the filter constructors that
+// panic on bad input like BloomFilter::new instead of returning a Result.
+pub(crate) fn expect_params(size: usize, fp_rate: f64) -> BloomFilterParams {
+    BloomFilterBuilder::new()
+        .expected_items(size)
+        .fp_rate(fp_rate)
+        .params()
+        .unwrap_or_else(|err| panic!("invalid bloom filter parameters: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bloom_filter::num_bits;
+
+    #[test]
+    fn test_matches_new() {
+        let filter = BloomFilterBuilder::new()
+            .expected_items(1000)
+            .fp_rate(0.01)
+            .build::<u64>()
+            .unwrap();
+        let plain = BloomFilter::<u64>::new(1000, 0.01);
+        assert_eq!(filter.len(), plain.len());
+        assert_eq!(filter.hashes(), plain.hashes());
+        assert_eq!(filter.strategy(), HashStrategy::Murmur3);
+    }
+
+    #[test]
+    fn test_effective_fp_rate() {
+        let params = BloomFilterBuilder::new()
+            .expected_items(1000)
+            .fp_rate(0.01)
+            .strategy(HashStrategy::XxHash)
+            .params()
+            .unwrap();
+        assert_eq!(params.bits, num_bits(1000, 0.01));
+        assert_eq!(params.strategy, HashStrategy::XxHash);
+        let rate = params.fp_rate.unwrap();
+        assert!((rate - 0.01).abs() < 0.0005, "rate {}", rate);
+
+        let params = BloomFilterBuilder::new()
+            .bits(1 << 16)
+            .hashes(5)
+            .params()
+            .unwrap();
+        assert_eq!(
+            (params.bits, params.hashes, params.fp_rate),
+            (1 << 16, 5, None)
+        );
+    }
+
+    #[test]
+    fn test_bits_with_expected_items() {
+        let params = BloomFilterBuilder::new()
+            .bits(9586)
+            .expected_items(1000)
+            .params()
+            .unwrap();
+        assert_eq!(params.hashes, 7);
+        assert!(params.fp_rate.unwrap() < 0.011);
+    }
+
+    #[test]
+    fn test_derived_hashes_are_capped() {
+        let params = BloomFilterBuilder::new()
+            .bits(1 << 30)
+            .expected_items(1)
+            .params()
+            .unwrap();
+        assert_eq!(params.hashes, MAX_HASHES);
+        assert!(params.fp_rate.unwrap() < 1e-300);
+        let params = BloomFilterBuilder::new()
+            .bits(100)
+            .hashes(MAX_HASHES)
+            .params()
+            .unwrap();
+        assert_eq!(params.hashes, MAX_HASHES);
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        // The whole budget when it is the only size given.
+        let params = BloomFilterBuilder::new()
+            .expected_items(1000)
+            .memory_budget(1024)
+            .params()
+            .unwrap();
+        assert_eq!(params.bits, 8192);
+        assert!(params.fp_rate.unwrap() < 0.02);
+
+        // A cap otherwise.
+        let builder = BloomFilterBuilder::new()
+            .expected_items(1_000_000)
+            .fp_rate(0.01)
+            .memory_budget(1024);
+        assert_eq!(
+            builder.params(),
+            Err(BloomFilterError::ExceedsMemoryBudget {
+                required: 1_198_136,
+                budget: 1024
+            })
+        );
+        assert_eq!(
+            BloomFilterBuilder::new()
+                .expected_items(10)
+                .memory_budget(4)
+                .params(),
+            Err(BloomFilterError::ExceedsMemoryBudget {
+                required: 8,
+                budget: 4
+            })
+        );
+        assert!(BloomFilterBuilder::new()
+            .bits(8192)
+            .hashes(3)
+            .memory_budget(1024)
+            .build::<u64>()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_invalid_parameters() {
+        let items = BloomFilterBuilder::new().expected_items(1000);
+        for rate in [0.0, 1.0, 1.5, -0.1, f64::NAN] {
+            assert!(matches!(
+                items.clone().fp_rate(rate).params(),
+                Err(BloomFilterError::InvalidFpRate(_))
+            ));
+        }
+        let cases = [
+            (
+                BloomFilterBuilder::new().expected_items(0).fp_rate(0.01),
+                BloomFilterError::ZeroItems,
+            ),
+            (
+                BloomFilterBuilder::new().bits(0).hashes(3),
+                BloomFilterError::ZeroBits,
+            ),
+            (
+                BloomFilterBuilder::new().bits(100).hashes(0),
+                BloomFilterError::ZeroHashes,
+            ),
+            (
+                BloomFilterBuilder::new().bits(100).hashes(MAX_HASHES + 1),
+                BloomFilterError::TooManyHashes(MAX_HASHES + 1),
+            ),
+            (
+                items.clone().memory_budget(0),
+                BloomFilterError::ZeroMemoryBudget,
+            ),
+            (
+                BloomFilterBuilder::new().fp_rate(0.01),
+                BloomFilterError::MissingParameter("expected_items or bits"),
+            ),
+            (
+                items.clone(),
+                BloomFilterError::MissingParameter(
+                    "fp_rate or memory_budget to go with expected_items",
+                ),
+            ),
+            (
+                BloomFilterBuilder::new().bits(100),
+                BloomFilterError::MissingParameter("hashes or expected_items to go with bits"),
+            ),
+            (
+                items.clone().fp_rate(0.01).bits(100),
+                BloomFilterError::ConflictingParameters(
+                    "bits and fp_rate with expected_items both fix the size",
+                ),
+            ),
+            (
+                BloomFilterBuilder::new()
+                    .expected_items(usize::MAX)
+                    .fp_rate(1e-9),
+                BloomFilterError::TooLarge,
+            ),
+            (
+                BloomFilterBuilder::new().bits(1 << 16).fp_rate(0.01),
+                BloomFilterError::ConflictingParameters(
+                    "fp_rate has no effect with bits and no expected_items",
+                ),
+            ),
+            (
+                BloomFilterBuilder::new()
+                    .bits(1 << 16)
+                    .hashes(5)
+                    .fp_rate(0.01),
+                BloomFilterError::ConflictingParameters(
+                    "fp_rate has no effect with bits and no expected_items",
+                ),
+            ),
+        ];
+        for (builder, err) in cases {
+            assert_eq!(builder.params(), Err(err));
+            assert!(builder.build::<u64>().is_err());
+        }
+    }
+
+    #[test]
+    fn test_wide_filters_avoid_murmur3() {
+        let bits = BloomFilterBuilder::new().hashes(3);
+        let params = bits.clone().bits(1 << 32).params().unwrap();
+        assert_eq!(params.strategy, HashStrategy::Murmur3);
+        let params = bits.clone().bits((1 << 32) + 1).params().unwrap();
+        assert_eq!(params.strategy, HashStrategy::DoubleHashing);
+        let params = bits
+            .clone()
+            .bits(1 << 33)
+            .strategy(HashStrategy::XxHash)
+            .params()
+            .unwrap();
+        assert_eq!(params.strategy, HashStrategy::XxHash);
+        assert_eq!(
+            bits.bits(1 << 33).strategy(HashStrategy::Murmur3).params(),
+            Err(BloomFilterError::ConflictingParameters(
+                "murmur3 only reaches the first 2^32 bits"
+            ))
+        );
+
+        let params = BloomFilterBuilder::new()
+            .expected_items(1_000_000_000)
+            .fp_rate(0.001)
+            .params()
+            .unwrap();
+        assert!(params.bits > 1 << 32);
+        assert_eq!(params.strategy, HashStrategy::DoubleHashing);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected items must be positive")]
+    fn test_new_panics_with_message() {
+        BloomFilter::<u64>::new(0, 0.01);
+    }
+}
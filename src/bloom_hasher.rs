@@ -0,0 +1,259 @@
+use fasthash::{murmur3, xx, FastHash};
+use siphasher::sip::SipHasher24;
+
+// A way of turning a key into the k bit positions a bloom filter sets or
+// checks for it.
+pub trait BloomHasher {
+    fn strategy(&self) -> HashStrategy;
+
+    // Calls `f` with each of the `hashes` positions for `value`, all in 0..m.
+    // Stops as soon as `f` returns false and reports whether it never did.
+    fn for_each_index<F: FnMut(usize) -> bool>(
+        &self,
+        value: &[u8],
+        hashes: usize,
+        m: usize,
+        f: F,
+    ) -> bool;
+}
+
+// k independent 32 bit murmur3 hashes, seeded with 0..k.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Murmur3;
+
+// k independent 64 bit xxHash hashes, seeded with 0..k.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct XxHash;
+
+// k independent SipHash-2-4 hashes, keyed with (i, 0) for i in 0..k.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SipHash;
+
+// Enhanced double hashing (Dillinger-Manolios): one 128 bit murmur3 hash is
+// split into h1 and h2, and position i is h1 + i * h2 + (i^3 - i) / 6 mod m.
+// The cubic term keeps the positions apart when h2 shares a factor with m,
+// and h2 is taken as 1 when it is 0 mod m, which would otherwise put all k
+// positions on one bit. Two hashes instead of k makes this the cheapest
+// strategy for filters with many hash functions.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DoubleHashing;
+
+impl BloomHasher for Murmur3 {
+    fn strategy(&self) -> HashStrategy {
+        HashStrategy::Murmur3
+    }
+
+    fn for_each_index<F: FnMut(usize) -> bool>(
+        &self,
+        value: &[u8],
+        hashes: usize,
+        m: usize,
+        mut f: F,
+    ) -> bool {
+        (0..hashes).all(|i| {
+            let hash = murmur3::hash32_with_seed(value, i as u32) as u64;
+            f((hash % m as u64) as usize)
+        })
+    }
+}
+
+impl BloomHasher for XxHash {
+    fn strategy(&self) -> HashStrategy {
+        HashStrategy::XxHash
+    }
+
+    fn for_each_index<F: FnMut(usize) -> bool>(
+        &self,
+        value: &[u8],
+        hashes: usize,
+        m: usize,
+        mut f: F,
+    ) -> bool {
+        (0..hashes).all(|i| {
+            let hash = xx::hash64_with_seed(value, i as u64);
+            f((hash % m as u64) as usize)
+        })
+    }
+}
+
+impl BloomHasher for SipHash {
+    fn strategy(&self) -> HashStrategy {
+        HashStrategy::SipHash
+    }
+
+    fn for_each_index<F: FnMut(usize) -> bool>(
+        &self,
+        value: &[u8],
+        hashes: usize,
+        m: usize,
+        mut f: F,
+    ) -> bool {
+        (0..hashes).all(|i| {
+            let hash = SipHasher24::new_with_keys(i as u64, 0).hash(value);
+            f((hash % m as u64) as usize)
+        })
+    }
+}
+
+impl BloomHasher for DoubleHashing {
+    fn strategy(&self) -> HashStrategy {
+        HashStrategy::DoubleHashing
+    }
+
+    fn for_each_index<F: FnMut(usize) -> bool>(
+        &self,
+        value: &[u8],
+        hashes: usize,
+        m: usize,
+        mut f: F,
+    ) -> bool {
+        let m = m as u64;
+        let (h1, h2) = double_hash(value);
+        let mut index = h1 % m;
+        let mut step = (h2 % m).max(1);
+        (0..hashes as u64).all(|i| {
+            let position = index as usize;
+            index = (index + step) % m;
+            step = (step + i + 1) % m;
+            f(position)
+        })
+    }
+}
+
+// The two 64 bit halves of the x64 128 bit murmur3 hash of `value`. The
+// x64 variant is named explicitly so 32 bit targets get the same bits.
+pub(crate) fn double_hash(value: &[u8]) -> (u64, u64) {
+    let hash = murmur3::Hash128_x64::hash_with_seed(value, 0);
+    (hash as u64, (hash >> 64) as u64)
+}
+
+// The strategies shipped with the crate. Filters keep the one they were built
+// with so they are always queried the same way. The discriminant is stable
+// and is what gets stored when a filter is saved.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum HashStrategy {
+    #[default]
+    Murmur3 = 0,
+    XxHash = 1,
+    SipHash = 2,
+    DoubleHashing = 3,
+}
+
+impl HashStrategy {
+    pub fn from_u8(value: u8) -> Option<HashStrategy> {
+        match value {
+            0 => Some(HashStrategy::Murmur3),
+            1 => Some(HashStrategy::XxHash),
+            2 => Some(HashStrategy::SipHash),
+            3 => Some(HashStrategy::DoubleHashing),
+            _ => None,
+        }
+    }
+}
+
+impl BloomHasher for HashStrategy {
+    fn strategy(&self) -> HashStrategy {
+        *self
+    }
+
+    #[inline]
+    fn for_each_index<F: FnMut(usize) -> bool>(
+        &self,
+        value: &[u8],
+        hashes: usize,
+        m: usize,
+        f: F,
+    ) -> bool {
+        match self {
+            HashStrategy::Murmur3 => Murmur3.for_each_index(value, hashes, m, f),
+            HashStrategy::XxHash => XxHash.for_each_index(value, hashes, m, f),
+            HashStrategy::SipHash => SipHash.for_each_index(value, hashes, m, f),
+            HashStrategy::DoubleHashing => DoubleHashing.for_each_index(value, hashes, m, f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STRATEGIES: [HashStrategy; 4] = [
+        HashStrategy::Murmur3,
+        HashStrategy::XxHash,
+        HashStrategy::SipHash,
+        HashStrategy::DoubleHashing,
+    ];
+
+    fn collect(strategy: HashStrategy, value: &[u8], hashes: usize, m: usize) -> Vec<usize> {
+        let mut indexes = Vec::new();
+        strategy.for_each_index(value, hashes, m, |i| {
+            indexes.push(i);
+            true
+        });
+        indexes
+    }
+
+    #[test]
+    fn test_indexes_in_range() {
+        for strategy in STRATEGIES {
+            let indexes = collect(strategy, b"test", 7, 1000);
+            assert_eq!(indexes.len(), 7);
+            assert!(indexes.iter().all(|&i| i < 1000));
+            assert_eq!(indexes, collect(strategy, b"test", 7, 1000));
+        }
+    }
+
+    #[test]
+    fn test_strategies_differ() {
+        let indexes: Vec<_> = STRATEGIES
+            .iter()
+            .map(|&s| collect(s, b"test", 7, 1 << 20))
+            .collect();
+        for i in 0..indexes.len() {
+            for j in i + 1..indexes.len() {
+                assert_ne!(indexes[i], indexes[j]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_stops_early() {
+        let mut calls = 0;
+        let all = DoubleHashing.for_each_index(b"test", 7, 1000, |_| {
+            calls += 1;
+            calls < 3
+        });
+        assert!(!all);
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn test_strategy_round_trip() {
+        for strategy in STRATEGIES {
+            assert_eq!(HashStrategy::from_u8(strategy as u8), Some(strategy));
+        }
+        assert_eq!(HashStrategy::from_u8(4), None);
+    }
+
+    // A key whose h2 is a multiple of m used to get all k positions on the
+    // same bit.
+    #[test]
+    fn test_double_hashing_spreads_zero_step() {
+        let (_, h2) = double_hash(b"test");
+        let indexes = collect(HashStrategy::DoubleHashing, b"test", 7, h2 as usize);
+        let mut distinct = indexes.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+        assert_eq!(distinct.len(), 7, "{:?}", indexes);
+
+        let key = (0u32..)
+            .map(|i| i.to_le_bytes())
+            .find(|key| double_hash(key).1.is_multiple_of(64))
+            .unwrap();
+        let mut indexes = collect(HashStrategy::DoubleHashing, &key, 7, 64);
+        indexes.sort_unstable();
+        indexes.dedup();
+        assert_eq!(indexes.len(), 7);
+    }
+}
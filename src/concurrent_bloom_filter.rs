@@ -0,0 +1,202 @@
+use crate::bloom_filter::{BloomFilter, KeyBytes};
+use crate::bloom_filter_builder::expect_params;
+use crate::bloom_hasher::{BloomHasher, HashStrategy};
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// ConcurrentBloomFilter lays its bits out exactly like BloomFilter but keeps
+// them in AtomicU64 words, so inserts only need `&self` and the filter can be
+// shared between threads through an `Arc` or a scoped borrow. Setting a bit is
+// a relaxed `fetch_or`: bits are only ever set, never cleared, so no ordering
+// between different bits is needed. A lookup racing an insert of the same key
+// may or may not see it, but once `insert` has returned on one thread every
+// later `contains` on a thread that synchronised with it sees the key.
+pub struct ConcurrentBloomFilter<T: ?Sized> {
+    words: Vec<AtomicU64>,
+    bits: usize,
+    hashes: usize,
+    strategy: HashStrategy,
+    _key: PhantomData<fn(&T)>,
+}
+
+impl<T: ?Sized> ConcurrentBloomFilter<T> {
+    pub fn new(size: usize, fp_rate: f64) -> ConcurrentBloomFilter<T> {
+        ConcurrentBloomFilter::with_strategy(size, fp_rate, HashStrategy::default())
+    }
+
+    pub fn with_strategy(
+        size: usize,
+        fp_rate: f64,
+        strategy: HashStrategy,
+    ) -> ConcurrentBloomFilter<T> {
+        let params = expect_params(size, fp_rate);
+        ConcurrentBloomFilter {
+            words: (0..params.bits.div_ceil(64))
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            bits: params.bits,
+            hashes: params.hashes,
+            strategy,
+            _key: PhantomData,
+        }
+    }
+
+    pub fn insert_bytes(&self, value: &[u8]) {
+        self.strategy
+            .for_each_index(value, self.hashes, self.bits, |pos| {
+                let mask = 1 << (pos % 64);
+                // Skip the read-modify-write when the bit is already set, which
+                // keeps hot cache lines shared instead of bouncing them.
+                let word = &self.words[pos / 64];
+                if word.load(Ordering::Relaxed) & mask == 0 {
+                    word.fetch_or(mask, Ordering::Relaxed);
+                }
+                true
+            });
+    }
+
+    pub fn contains_bytes(&self, value: &[u8]) -> bool {
+        self.strategy
+            .for_each_index(value, self.hashes, self.bits, |pos| {
+                self.words[pos / 64].load(Ordering::Relaxed) & (1 << (pos % 64)) != 0
+            })
+    }
+
+    // Number of bits in the filter.
+    pub fn len(&self) -> usize {
+        self.bits
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+
+    pub fn hashes(&self) -> usize {
+        self.hashes
+    }
+
+    pub fn strategy(&self) -> HashStrategy {
+        self.strategy
+    }
+
+    // A plain BloomFilter with the bits set so far. Inserts running at the
+    // same time may or may not make it into the copy.
+    pub fn to_bloom_filter(&self) -> BloomFilter<T> {
+        let words = self
+            .words
+            .iter()
+            .map(|word| word.load(Ordering::Relaxed))
+            .collect();
+        BloomFilter::from_words(words, self.bits, self.hashes, self.strategy)
+    }
+}
+
+impl<T: Hash + ?Sized> ConcurrentBloomFilter<T> {
+    pub fn insert(&self, value: &T) {
+        self.insert_bytes(&KeyBytes::of(value));
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.contains_bytes(&KeyBytes::of(value))
+    }
+}
+
+impl<T: ?Sized> From<BloomFilter<T>> for ConcurrentBloomFilter<T> {
+    fn from(filter: BloomFilter<T>) -> Self {
+        let bits = filter.len();
+        let hashes = filter.hashes();
+        let strategy = filter.strategy();
+        ConcurrentBloomFilter {
+            words: filter
+                .into_words()
+                .into_iter()
+                .map(AtomicU64::new)
+                .collect(),
+            bits,
+            hashes,
+            strategy,
+            _key: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized> From<ConcurrentBloomFilter<T>> for BloomFilter<T> {
+    fn from(filter: ConcurrentBloomFilter<T>) -> Self {
+        let words = filter
+            .words
+            .into_iter()
+            .map(AtomicU64::into_inner)
+            .collect();
+        BloomFilter::from_words(words, filter.bits, filter.hashes, filter.strategy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn assert_send_sync<S: Send + Sync>() {}
+
+    #[test]
+    fn test_send_sync() {
+        assert_send_sync::<ConcurrentBloomFilter<str>>();
+        assert_send_sync::<ConcurrentBloomFilter<std::rc::Rc<u64>>>();
+    }
+
+    #[test]
+    fn test_insert() {
+        let filter = ConcurrentBloomFilter::new(2, 0.001);
+        filter.insert("test");
+        assert!(!filter.contains("bar"));
+        assert!(!filter.contains("foo"));
+        assert!(filter.contains("test"));
+    }
+
+    #[test]
+    fn test_threads() {
+        let filter = ConcurrentBloomFilter::<u64>::new(80_000, 0.01);
+        thread::scope(|scope| {
+            for t in 0..8 {
+                let filter = &filter;
+                scope.spawn(move || {
+                    for i in (t * 10_000)..((t + 1) * 10_000) {
+                        filter.insert(&i);
+                    }
+                });
+            }
+        });
+        for i in 0..80_000 {
+            assert!(filter.contains(&i));
+        }
+    }
+
+    #[test]
+    fn test_matches_bloom_filter() {
+        let mut plain = BloomFilter::<u64>::with_strategy(1000, 0.01, HashStrategy::XxHash);
+        let concurrent =
+            ConcurrentBloomFilter::<u64>::with_strategy(1000, 0.01, HashStrategy::XxHash);
+        for i in 0..1000 {
+            plain.insert(&i);
+            concurrent.insert(&i);
+        }
+        assert_eq!(concurrent.to_bloom_filter().to_bytes(), plain.to_bytes());
+
+        let round_trip = BloomFilter::from(ConcurrentBloomFilter::from(plain.clone()));
+        assert_eq!(round_trip.to_bytes(), plain.to_bytes());
+        assert_eq!(BloomFilter::from(concurrent).to_bytes(), plain.to_bytes());
+    }
+
+    #[test]
+    #[should_panic(expected = "expected items must be positive")]
+    fn test_zero_size_panics() {
+        ConcurrentBloomFilter::<u64>::new(0, 0.01);
+    }
+
+    #[test]
+    #[should_panic(expected = "false positive rate must be between 0 and 1")]
+    fn test_bad_fp_rate_panics() {
+        ConcurrentBloomFilter::<u64>::with_strategy(100, 1.5, HashStrategy::XxHash);
+    }
+}
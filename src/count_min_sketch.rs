@@ -0,0 +1,459 @@
+use crate::bloom_filter::KeyBytes;
+use crate::bloom_hasher::{BloomHasher, Murmur3};
+use crate::serialization::{
+    checksum_words, from_slice, read_array, read_words, write_words, DecodeError,
+};
+use std::error::Error;
+use std::f64::consts::E;
+use std::fmt;
+use std::hash::Hash;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+
+// Saved sketches use the crate's usual 32 byte header:
+//
+//   offset  size  field
+//        0     4  magic "CMSK"
+//        4     2  format version, little endian
+//        6     1  flags, bit 0 set for conservative update
+//        7     1  reserved, zero
+//        8     8  width, little endian
+//       16     8  depth, little endian
+//       24     4  crc32 of bytes 0..24 and the payload
+//       28     4  reserved, zero
+//       32     -  total count, then the counters row by row, little endian
+//                 u64 words
+pub const MAGIC: [u8; 4] = *b"CMSK";
+pub const VERSION: u16 = 1;
+pub const HEADER_LEN: usize = 32;
+
+const CONSERVATIVE: u8 = 1;
+
+// Counters per row for an additive error of epsilon times the total count.
+// Panics unless epsilon is strictly between 0 and 1.
+pub fn width(epsilon: f64) -> usize {
+    // Written so NaN fails too.
+    assert!(
+        epsilon > 0.0 && epsilon < 1.0,
+        "epsilon must be between 0 and 1 exclusive, got {}",
+        epsilon
+    );
+    ((E / epsilon).ceil() as usize).max(1)
+}
+
+// Rows for the error bound to hold with probability 1 - delta. Panics unless
+// delta is strictly between 0 and 1.
+pub fn depth(delta: f64) -> usize {
+    assert!(
+        delta > 0.0 && delta < 1.0,
+        "delta must be between 0 and 1 exclusive, got {}",
+        delta
+    );
+    ((1.0 / delta).ln().ceil() as usize).max(1)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IncompatibleSketches {
+    Width(usize, usize),
+    Depth(usize, usize),
+}
+
+impl fmt::Display for IncompatibleSketches {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IncompatibleSketches::Width(a, b) => {
+                write!(f, "sketches have different widths: {} and {}", a, b)
+            }
+            IncompatibleSketches::Depth(a, b) => {
+                write!(f, "sketches have different depths: {} and {}", a, b)
+            }
+        }
+    }
+}
+
+impl Error for IncompatibleSketches {}
+
+// CountMinSketch (Cormode and Muthukrishnan, "An Improved Data Stream
+// Summary: The Count-Min Sketch and its Applications") keeps `depth` rows of
+// `width` counters. A key adds its count to one counter per row, picked with
+// the same seeded murmur3 hashes a default BloomFilter uses (row i hashes
+// with seed i), and its estimate is the smallest of those counters. Estimates
+// never undercount, and with probability 1 - delta overcount by at most
+// epsilon times the total of all counts.
+//
+// With conservative update an add only raises the key's counters as far as
+// the new estimate needs, which leaves less noise for other keys. Such a
+// sketch can't take negative updates, which this one doesn't offer anyway.
+pub struct CountMinSketch<T: ?Sized> {
+    counters: Vec<u64>,
+    width: usize,
+    depth: usize,
+    total: u64,
+    conservative: bool,
+    _key: PhantomData<fn(&T)>,
+}
+
+impl<T: ?Sized> CountMinSketch<T> {
+    // A sketch sized so estimates are within epsilon * total of the true
+    // count with probability 1 - delta.
+    pub fn new(epsilon: f64, delta: f64) -> CountMinSketch<T> {
+        CountMinSketch::with_dimensions(width(epsilon), depth(delta))
+    }
+
+    pub fn with_dimensions(width: usize, depth: usize) -> CountMinSketch<T> {
+        assert!(width > 0 && depth > 0, "width and depth must be positive");
+        let cells = width
+            .checked_mul(depth)
+            .expect("width times depth overflows usize");
+        CountMinSketch {
+            counters: vec![0; cells],
+            width,
+            depth,
+            total: 0,
+            conservative: false,
+            _key: PhantomData,
+        }
+    }
+
+    // Switches conservative update on or off for later adds.
+    pub fn set_conservative_update(&mut self, conservative: bool) {
+        self.conservative = conservative;
+    }
+
+    pub fn conservative_update(&self) -> bool {
+        self.conservative
+    }
+
+    // The counter index of the key in every row.
+    fn cells(&self, value: &[u8]) -> Vec<usize> {
+        let mut cells = Vec::with_capacity(self.depth);
+        Murmur3.for_each_index(value, self.depth, self.width, |column| {
+            cells.push(cells.len() * self.width + column);
+            true
+        });
+        cells
+    }
+
+    pub fn add_bytes(&mut self, value: &[u8], count: u64) {
+        let cells = self.cells(value);
+        self.total = self.total.saturating_add(count);
+        if self.conservative {
+            let target = cells
+                .iter()
+                .map(|&i| self.counters[i])
+                .min()
+                .unwrap()
+                .saturating_add(count);
+            for i in cells {
+                self.counters[i] = self.counters[i].max(target);
+            }
+        } else {
+            for i in cells {
+                self.counters[i] = self.counters[i].saturating_add(count);
+            }
+        }
+    }
+
+    pub fn estimate_bytes(&self, value: &[u8]) -> u64 {
+        self.cells(value)
+            .into_iter()
+            .map(|i| self.counters[i])
+            .min()
+            .unwrap()
+    }
+
+    pub fn check_compatible(&self, other: &CountMinSketch<T>) -> Result<(), IncompatibleSketches> {
+        if self.width != other.width {
+            return Err(IncompatibleSketches::Width(self.width, other.width));
+        }
+        if self.depth != other.depth {
+            return Err(IncompatibleSketches::Depth(self.depth, other.depth));
+        }
+        Ok(())
+    }
+
+    // Adds every count of `other` to this sketch, as if all its adds had been
+    // made here. Summed conservative sketches still never undercount.
+    pub fn merge(&mut self, other: &CountMinSketch<T>) -> Result<(), IncompatibleSketches> {
+        self.check_compatible(other)?;
+        for (counter, other) in self.counters.iter_mut().zip(&other.counters) {
+            *counter = counter.saturating_add(*other);
+        }
+        self.total = self.total.saturating_add(other.total);
+        Ok(())
+    }
+
+    // Sum of every count added.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    // The error bound as a fraction of the total, e / width.
+    pub fn epsilon(&self) -> f64 {
+        E / self.width as f64
+    }
+
+    // The probability of exceeding the error bound, e^-depth.
+    pub fn delta(&self) -> f64 {
+        (-(self.depth as f64)).exp()
+    }
+
+    fn header(&self) -> [u8; HEADER_LEN] {
+        let mut header = [0; HEADER_LEN];
+        header[0..4].copy_from_slice(&MAGIC);
+        header[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        header[6] = if self.conservative { CONSERVATIVE } else { 0 };
+        header[8..16].copy_from_slice(&(self.width as u64).to_le_bytes());
+        header[16..24].copy_from_slice(&(self.depth as u64).to_le_bytes());
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(&header[..24]);
+        checksum_words(&mut crc, &[self.total]);
+        checksum_words(&mut crc, &self.counters);
+        header[24..28].copy_from_slice(&crc.finalize().to_le_bytes());
+        header
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + (self.counters.len() + 1) * 8);
+        self.write_to(&mut bytes)
+            .expect("writing to a Vec never fails");
+        bytes
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.header())?;
+        write_words(&mut writer, &[self.total])?;
+        write_words(&mut writer, &self.counters)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<CountMinSketch<T>, DecodeError> {
+        from_slice(bytes, |reader| CountMinSketch::read_from(reader))
+    }
+
+    // Reads exactly one sketch, leaving anything after it in the reader.
+    pub fn read_from<R: Read>(mut reader: R) -> Result<CountMinSketch<T>, DecodeError> {
+        let header: [u8; HEADER_LEN] = read_array(&mut reader)?;
+        if header[0..4] != MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version != VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        if header[6] & !CONSERVATIVE != 0 || header[7] != 0 || header[28..32] != [0; 4] {
+            return Err(DecodeError::Corrupt("reserved header bytes are not zero"));
+        }
+        let dimension = |bytes: &[u8]| {
+            usize::try_from(u64::from_le_bytes(bytes.try_into().unwrap()))
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or(DecodeError::Corrupt("invalid sketch dimensions"))
+        };
+        let width = dimension(&header[8..16])?;
+        let depth = dimension(&header[16..24])?;
+        let cells = width
+            .checked_mul(depth)
+            .ok_or(DecodeError::Corrupt("invalid sketch dimensions"))?;
+        let expected = u32::from_le_bytes(header[24..28].try_into().unwrap());
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(&header[..24]);
+        let total = read_words(&mut reader, 1, &mut crc)?[0];
+        let counters = read_words(&mut reader, cells, &mut crc)?;
+        let found = crc.finalize();
+        if found != expected {
+            return Err(DecodeError::ChecksumMismatch { expected, found });
+        }
+        Ok(CountMinSketch {
+            counters,
+            width,
+            depth,
+            total,
+            conservative: header[6] & CONSERVATIVE != 0,
+            _key: PhantomData,
+        })
+    }
+}
+
+impl<T: Hash + ?Sized> CountMinSketch<T> {
+    pub fn add(&mut self, value: &T, count: u64) {
+        self.add_bytes(&KeyBytes::of(value), count);
+    }
+
+    pub fn estimate(&self, value: &T) -> u64 {
+        self.estimate_bytes(&KeyBytes::of(value))
+    }
+}
+
+impl<T: ?Sized> Clone for CountMinSketch<T> {
+    fn clone(&self) -> Self {
+        CountMinSketch {
+            counters: self.counters.clone(),
+            width: self.width,
+            depth: self.depth,
+            total: self.total,
+            conservative: self.conservative,
+            _key: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A skewed stream: key i appears 10_000 / (i + 1) times.
+    fn zipf_counts() -> Vec<(u64, u64)> {
+        (0..2000).map(|i| (i, 10_000 / (i + 1))).collect()
+    }
+
+    fn fill(sketch: &mut CountMinSketch<u64>, counts: &[(u64, u64)]) {
+        for &(key, count) in counts {
+            sketch.add(&key, count);
+        }
+    }
+
+    #[test]
+    fn test_add() {
+        let mut sketch = CountMinSketch::new(0.001, 0.01);
+        sketch.add("test", 3);
+        sketch.add("test", 2);
+        sketch.add("foo", 1);
+        assert_eq!(sketch.estimate("test"), 5);
+        assert_eq!(sketch.estimate("foo"), 1);
+        assert_eq!(sketch.estimate("bar"), 0);
+        assert_eq!(sketch.total(), 6);
+    }
+
+    #[test]
+    fn test_dimensions() {
+        let sketch = CountMinSketch::<u64>::new(0.01, 0.001);
+        assert_eq!(sketch.width(), 272);
+        assert_eq!(sketch.depth(), 7);
+        assert!(sketch.epsilon() <= 0.01);
+        assert!(sketch.delta() <= 0.001);
+    }
+
+    #[test]
+    #[should_panic(expected = "epsilon must be between 0 and 1 exclusive")]
+    fn test_zero_epsilon_panics() {
+        CountMinSketch::<u64>::new(0.0, 0.01);
+    }
+
+    #[test]
+    #[should_panic(expected = "delta must be between 0 and 1 exclusive")]
+    fn test_nan_delta_panics() {
+        CountMinSketch::<u64>::new(0.01, f64::NAN);
+    }
+
+    #[test]
+    #[should_panic(expected = "width times depth overflows usize")]
+    fn test_dimensions_overflow_panics() {
+        CountMinSketch::<u64>::with_dimensions(usize::MAX, 2);
+    }
+
+    #[test]
+    fn test_error_bound() {
+        let counts = zipf_counts();
+        let mut sketch = CountMinSketch::new(0.001, 0.01);
+        fill(&mut sketch, &counts);
+        let bound = (sketch.epsilon() * sketch.total() as f64) as u64;
+        let mut over_bound = 0;
+        for &(key, count) in &counts {
+            let estimate = sketch.estimate(&key);
+            assert!(estimate >= count);
+            if estimate - count > bound {
+                over_bound += 1;
+            }
+        }
+        assert!((over_bound as f64 / counts.len() as f64) < 0.01);
+    }
+
+    #[test]
+    fn test_conservative_update() {
+        let counts = zipf_counts();
+        let mut plain = CountMinSketch::with_dimensions(200, 4);
+        let mut conservative = CountMinSketch::with_dimensions(200, 4);
+        conservative.set_conservative_update(true);
+        // One at a time, so conservative update has something to save.
+        for &(key, count) in &counts {
+            for _ in 0..count {
+                plain.add(&key, 1);
+                conservative.add(&key, 1);
+            }
+        }
+        let mut plain_error = 0;
+        let mut conservative_error = 0;
+        for &(key, count) in &counts {
+            let estimate = conservative.estimate(&key);
+            assert!(estimate >= count);
+            assert!(estimate <= plain.estimate(&key));
+            plain_error += plain.estimate(&key) - count;
+            conservative_error += estimate - count;
+        }
+        assert!(conservative_error < plain_error * 3 / 4);
+    }
+
+    #[test]
+    fn test_merge() {
+        let counts = zipf_counts();
+        let (left, right) = counts.split_at(1000);
+        let mut whole = CountMinSketch::new(0.001, 0.01);
+        let mut a = CountMinSketch::new(0.001, 0.01);
+        let mut b = CountMinSketch::new(0.001, 0.01);
+        fill(&mut whole, &counts);
+        fill(&mut a, left);
+        fill(&mut b, right);
+        a.merge(&b).unwrap();
+        assert_eq!(a.counters, whole.counters);
+        assert_eq!(a.total(), whole.total());
+
+        let narrow = CountMinSketch::<u64>::new(0.01, 0.01);
+        assert_eq!(
+            a.merge(&narrow),
+            Err(IncompatibleSketches::Width(2719, 272))
+        );
+        let shallow = CountMinSketch::<u64>::new(0.001, 0.1);
+        assert_eq!(a.merge(&shallow), Err(IncompatibleSketches::Depth(5, 3)));
+    }
+
+    #[test]
+    fn test_serialization() {
+        let mut sketch = CountMinSketch::<u64>::new(0.01, 0.01);
+        sketch.set_conservative_update(true);
+        fill(&mut sketch, &zipf_counts());
+        let bytes = sketch.to_bytes();
+        assert_eq!(
+            bytes.len(),
+            HEADER_LEN + (sketch.width() * sketch.depth() + 1) * 8
+        );
+        let loaded = CountMinSketch::<u64>::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.counters, sketch.counters);
+        assert_eq!(loaded.total(), sketch.total());
+        assert!(loaded.conservative_update());
+
+        assert!(matches!(
+            CountMinSketch::<u64>::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(DecodeError::Truncated)
+        ));
+        let mut corrupt = bytes.clone();
+        corrupt[HEADER_LEN + 10] ^= 1;
+        assert!(matches!(
+            CountMinSketch::<u64>::from_bytes(&corrupt),
+            Err(DecodeError::ChecksumMismatch { .. })
+        ));
+        let mut corrupt = bytes;
+        corrupt[8..16].copy_from_slice(&0u64.to_le_bytes());
+        assert!(matches!(
+            CountMinSketch::<u64>::from_bytes(&corrupt),
+            Err(DecodeError::Corrupt(_))
+        ));
+    }
+}
@@ -0,0 +1,169 @@
+use crate::bloom_filter::KeyBytes;
+use crate::bloom_filter_builder::expect_params;
+use crate::bloom_hasher::{BloomHasher, Murmur3};
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+// Largest value a 4 bit counter can hold. A counter that reaches it sticks
+// there: we no longer know how many keys share the slot, so decrementing it
+// could introduce false negatives.
+const MAX_COUNT: u8 = 0x0f;
+
+// CountingBloomFilter replaces every bit of a BloomFilter with a 4 bit
+// saturating counter, two counters per byte, so keys can be removed again.
+pub struct CountingBloomFilter<T: ?Sized> {
+    counters: Vec<u8>,
+    slots: usize,
+    hashes: usize,
+    _key: PhantomData<fn(&T)>,
+}
+
+impl<T: ?Sized> CountingBloomFilter<T> {
+    pub fn new(size: usize, fp_rate: f64) -> CountingBloomFilter<T> {
+        let params = expect_params(size, fp_rate);
+        CountingBloomFilter {
+            counters: vec![0; params.bits.div_ceil(2)],
+            slots: params.bits,
+            hashes: params.hashes,
+            _key: PhantomData,
+        }
+    }
+
+    #[inline]
+    fn counter(&self, slot: usize) -> u8 {
+        (self.counters[slot / 2] >> ((slot % 2) * 4)) & MAX_COUNT
+    }
+
+    #[inline]
+    fn set_counter(&mut self, slot: usize, value: u8) {
+        let shift = (slot % 2) * 4;
+        let byte = &mut self.counters[slot / 2];
+        *byte = (*byte & !(MAX_COUNT << shift)) | (value << shift);
+    }
+
+    // The counter slots of a key, hashed like a default BloomFilter.
+    fn slots(&self, value: &[u8]) -> Vec<usize> {
+        let mut slots = Vec::with_capacity(self.hashes);
+        Murmur3.for_each_index(value, self.hashes, self.slots, |slot| {
+            slots.push(slot);
+            true
+        });
+        slots
+    }
+
+    pub fn insert_bytes(&mut self, value: &[u8]) {
+        for slot in self.slots(value) {
+            let count = self.counter(slot);
+            if count < MAX_COUNT {
+                self.set_counter(slot, count + 1);
+            }
+        }
+    }
+
+    // Removes one occurrence of the key. Returns false, leaving the filter
+    // untouched, when the key is definitely not present.
+    pub fn remove_bytes(&mut self, value: &[u8]) -> bool {
+        if !self.contains_bytes(value) {
+            return false;
+        }
+        for slot in self.slots(value) {
+            let count = self.counter(slot);
+            if count < MAX_COUNT {
+                self.set_counter(slot, count - 1);
+            }
+        }
+        true
+    }
+
+    pub fn contains_bytes(&self, value: &[u8]) -> bool {
+        Murmur3.for_each_index(value, self.hashes, self.slots, |slot| {
+            self.counter(slot) > 0
+        })
+    }
+
+    // Upper bound on how many times the key was inserted: the smallest of its
+    // counters. Saturated counters cap the estimate at 15.
+    pub fn count_bytes(&self, value: &[u8]) -> u8 {
+        self.slots(value)
+            .into_iter()
+            .map(|slot| self.counter(slot))
+            .min()
+            .unwrap_or(0)
+    }
+}
+
+impl<T: Hash + ?Sized> CountingBloomFilter<T> {
+    pub fn insert(&mut self, value: &T) {
+        self.insert_bytes(&KeyBytes::of(value));
+    }
+
+    pub fn remove(&mut self, value: &T) -> bool {
+        self.remove_bytes(&KeyBytes::of(value))
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.contains_bytes(&KeyBytes::of(value))
+    }
+
+    pub fn count(&self, value: &T) -> u8 {
+        self.count_bytes(&KeyBytes::of(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_remove() {
+        let mut filter = CountingBloomFilter::new(10, 0.001);
+        filter.insert("test");
+        filter.insert("foo");
+        assert!(filter.contains("test"));
+        assert!(filter.contains("foo"));
+        assert!(!filter.contains("bar"));
+
+        assert!(filter.remove("test"));
+        assert!(!filter.contains("test"));
+        assert!(filter.contains("foo"));
+        assert!(!filter.remove("bar"));
+    }
+
+    #[test]
+    fn test_count() {
+        let mut filter = CountingBloomFilter::<u32>::new(10, 0.001);
+        for _ in 0..3 {
+            filter.insert(&7);
+        }
+        assert_eq!(filter.count(&7), 3);
+        assert_eq!(filter.count(&8), 0);
+        filter.remove(&7);
+        assert_eq!(filter.count(&7), 2);
+    }
+
+    #[test]
+    fn test_saturation() {
+        let mut filter = CountingBloomFilter::<u32>::new(10, 0.001);
+        for _ in 0..20 {
+            filter.insert(&1);
+        }
+        assert_eq!(filter.count(&1), MAX_COUNT);
+        // Saturated counters never go down, so the key can't be lost.
+        for _ in 0..20 {
+            filter.remove(&1);
+        }
+        assert!(filter.contains(&1));
+    }
+
+    #[test]
+    #[should_panic(expected = "expected items must be positive")]
+    fn test_zero_size_panics() {
+        CountingBloomFilter::<u64>::new(0, 0.01);
+    }
+
+    #[test]
+    #[should_panic(expected = "false positive rate must be between 0 and 1")]
+    fn test_bad_fp_rate_panics() {
+        CountingBloomFilter::<u64>::new(100, 0.0);
+    }
+}
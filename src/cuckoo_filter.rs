@@ -0,0 +1,362 @@
+use crate::bloom_filter::KeyBytes;
+use crate::bloom_hasher::double_hash;
+use bitvec::prelude::*;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+// Fingerprints per bucket. Four gives a 95% load factor before inserts start
+// failing (Fan et al. "Cuckoo Filter: Practically Better Than Bloom").
+pub const BUCKET_SIZE: usize = 4;
+// How many fingerprints an insert may relocate before giving up.
+pub const MAX_KICKS: usize = 500;
+const MAX_LOAD: f64 = 0.95;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CuckooFilterError {
+    // No room is left for the fingerprint. The filter is unchanged and every
+    // key inserted before is still found.
+    Full,
+}
+
+impl fmt::Display for CuckooFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CuckooFilterError::Full => write!(f, "cuckoo filter is full"),
+        }
+    }
+}
+
+impl Error for CuckooFilterError {}
+
+// Fingerprint bits for a target false positive rate: a lookup compares
+// against 2 * BUCKET_SIZE fingerprints, each matching with probability
+// 2^-f, so f = ceil(log2(2 * b / fp_rate)).
+pub fn fingerprint_bits(fp_rate: f64) -> usize {
+    ((2.0 * BUCKET_SIZE as f64 / fp_rate).log2().ceil() as usize).clamp(1, 32)
+}
+
+// Buckets needed to hold `size` fingerprints below the maximum load. Always
+// a power of two so the alternate bucket can be found with an xor.
+pub fn num_buckets(size: usize) -> usize {
+    ((size as f64 / (BUCKET_SIZE as f64 * MAX_LOAD)).ceil() as usize)
+        .max(1)
+        .next_power_of_two()
+}
+
+// CuckooFilter stores a short fingerprint of every key in one of two buckets.
+// The second bucket is derived from the first and the fingerprint alone, so
+// fingerprints can be moved between their two buckets without the key, which
+// is what makes room on insert and allows deletes. The fingerprints are bit
+// packed, f bits each, and 0 marks an empty slot.
+pub struct CuckooFilter<T: ?Sized> {
+    slots: BitVec<u64, Lsb0>,
+    bucket_mask: usize,
+    fingerprint_bits: usize,
+    len: usize,
+    // A fingerprint evicted by an insert that ran out of kicks. Keeping it
+    // here means a failed relocation never drops a key.
+    victim: Option<(usize, u32)>,
+    rng: u64,
+    _key: PhantomData<fn(&T)>,
+}
+
+impl<T: ?Sized> CuckooFilter<T> {
+    // Panics when size is 0 or fp_rate isn't strictly between 0 and 1.
+    pub fn new(size: usize, fp_rate: f64) -> CuckooFilter<T> {
+        assert!(size > 0, "expected items must be positive");
+        assert!(
+            fp_rate > 0.0 && fp_rate < 1.0,
+            "false positive rate must be between 0 and 1 exclusive, got {}",
+            fp_rate
+        );
+        let buckets = num_buckets(size);
+        let f = fingerprint_bits(fp_rate);
+        CuckooFilter {
+            slots: bitvec![u64, Lsb0; 0; buckets * BUCKET_SIZE * f],
+            bucket_mask: buckets - 1,
+            fingerprint_bits: f,
+            len: 0,
+            victim: None,
+            rng: 0x2545_f491_4f6c_dd1d,
+            _key: PhantomData,
+        }
+    }
+
+    #[inline]
+    fn slot(&self, bucket: usize, i: usize) -> u32 {
+        let start = (bucket * BUCKET_SIZE + i) * self.fingerprint_bits;
+        self.slots[start..start + self.fingerprint_bits].load_le()
+    }
+
+    #[inline]
+    fn set_slot(&mut self, bucket: usize, i: usize, fingerprint: u32) {
+        let start = (bucket * BUCKET_SIZE + i) * self.fingerprint_bits;
+        self.slots[start..start + self.fingerprint_bits].store_le(fingerprint);
+    }
+
+    // The first bucket and the fingerprint of a key. The fingerprint is never
+    // 0, that value marks an empty slot.
+    #[inline]
+    fn locate(&self, value: &[u8]) -> (usize, u32) {
+        let (h1, h2) = double_hash(value);
+        let mask = (1u64 << self.fingerprint_bits) - 1;
+        let fingerprint = (h2 & mask).max(1) as u32;
+        (h1 as usize & self.bucket_mask, fingerprint)
+    }
+
+    // The other bucket a fingerprint may live in. Applying it twice gives
+    // back the starting bucket.
+    #[inline]
+    fn alt_bucket(&self, bucket: usize, fingerprint: u32) -> usize {
+        let hash = (fingerprint as u64).wrapping_mul(0x5bd1_e995) as usize;
+        (bucket ^ hash) & self.bucket_mask
+    }
+
+    fn next_random(&mut self) -> usize {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng as usize
+    }
+
+    fn try_put(&mut self, bucket: usize, fingerprint: u32) -> bool {
+        for i in 0..BUCKET_SIZE {
+            if self.slot(bucket, i) == 0 {
+                self.set_slot(bucket, i, fingerprint);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn bucket_contains(&self, bucket: usize, fingerprint: u32) -> bool {
+        (0..BUCKET_SIZE).any(|i| self.slot(bucket, i) == fingerprint)
+    }
+
+    fn bucket_remove(&mut self, bucket: usize, fingerprint: u32) -> bool {
+        for i in 0..BUCKET_SIZE {
+            if self.slot(bucket, i) == fingerprint {
+                self.set_slot(bucket, i, 0);
+                return true;
+            }
+        }
+        false
+    }
+
+    // Places a fingerprint, kicking others to their alternate bucket when
+    // both of its buckets are full. Whatever is left holding when the kicks
+    // run out goes into the victim slot, which must be empty.
+    fn place(&mut self, bucket: usize, fingerprint: u32) {
+        let alt = self.alt_bucket(bucket, fingerprint);
+        if self.try_put(bucket, fingerprint) || self.try_put(alt, fingerprint) {
+            return;
+        }
+        let mut bucket = if self.next_random() & 1 == 0 {
+            bucket
+        } else {
+            alt
+        };
+        let mut fingerprint = fingerprint;
+        for _ in 0..MAX_KICKS {
+            let i = self.next_random() % BUCKET_SIZE;
+            let evicted = self.slot(bucket, i);
+            self.set_slot(bucket, i, fingerprint);
+            fingerprint = evicted;
+            bucket = self.alt_bucket(bucket, fingerprint);
+            if self.try_put(bucket, fingerprint) {
+                return;
+            }
+        }
+        self.victim = Some((bucket, fingerprint));
+    }
+
+    pub fn insert_bytes(&mut self, value: &[u8]) -> Result<(), CuckooFilterError> {
+        if self.victim.is_some() {
+            return Err(CuckooFilterError::Full);
+        }
+        let (bucket, fingerprint) = self.locate(value);
+        self.place(bucket, fingerprint);
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn contains_bytes(&self, value: &[u8]) -> bool {
+        let (bucket, fingerprint) = self.locate(value);
+        let alt = self.alt_bucket(bucket, fingerprint);
+        if let Some((victim_bucket, victim)) = self.victim {
+            if victim == fingerprint && (victim_bucket == bucket || victim_bucket == alt) {
+                return true;
+            }
+        }
+        self.bucket_contains(bucket, fingerprint) || self.bucket_contains(alt, fingerprint)
+    }
+
+    // Removes one copy of the key's fingerprint. Only delete keys that were
+    // inserted: deleting a false positive removes another key's fingerprint.
+    pub fn delete_bytes(&mut self, value: &[u8]) -> bool {
+        let (bucket, fingerprint) = self.locate(value);
+        let alt = self.alt_bucket(bucket, fingerprint);
+        let removed = match self.victim {
+            Some((victim_bucket, victim))
+                if victim == fingerprint && (victim_bucket == bucket || victim_bucket == alt) =>
+            {
+                self.victim = None;
+                true
+            }
+            _ => self.bucket_remove(bucket, fingerprint) || self.bucket_remove(alt, fingerprint),
+        };
+        if !removed {
+            return false;
+        }
+        self.len -= 1;
+        // A slot just freed up, give the victim another chance at a bucket.
+        if let Some((victim_bucket, victim)) = self.victim.take() {
+            self.place(victim_bucket, victim);
+        }
+        true
+    }
+
+    // Number of fingerprints stored.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Number of fingerprints the table has slots for.
+    pub fn capacity(&self) -> usize {
+        (self.bucket_mask + 1) * BUCKET_SIZE
+    }
+
+    pub fn load_factor(&self) -> f64 {
+        self.len as f64 / self.capacity() as f64
+    }
+
+    pub fn fingerprint_bits(&self) -> usize {
+        self.fingerprint_bits
+    }
+
+    // Upper bound on the false positive rate when the filter is full.
+    pub fn fp_rate(&self) -> f64 {
+        2.0 * BUCKET_SIZE as f64 / 2.0f64.powi(self.fingerprint_bits as i32)
+    }
+}
+
+impl<T: Hash + ?Sized> CuckooFilter<T> {
+    pub fn insert(&mut self, value: &T) -> Result<(), CuckooFilterError> {
+        self.insert_bytes(&KeyBytes::of(value))
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.contains_bytes(&KeyBytes::of(value))
+    }
+
+    pub fn delete(&mut self, value: &T) -> bool {
+        self.delete_bytes(&KeyBytes::of(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert() {
+        let mut filter = CuckooFilter::new(2, 0.001);
+        filter.insert("test").unwrap();
+        assert!(!filter.contains("bar"));
+        assert!(!filter.contains("foo"));
+        assert!(!filter.contains("bazz"));
+        assert!(filter.contains("test"));
+    }
+
+    #[test]
+    fn test_delete() {
+        let mut filter = CuckooFilter::<u64>::new(1000, 0.001);
+        for i in 0..1000 {
+            filter.insert(&i).unwrap();
+        }
+        assert_eq!(filter.len(), 1000);
+        for i in (0..1000).step_by(2) {
+            assert!(filter.delete(&i));
+        }
+        assert_eq!(filter.len(), 500);
+        for i in (1..1000).step_by(2) {
+            assert!(filter.contains(&i));
+        }
+        let still_found = (0..1000).step_by(2).filter(|i| filter.contains(i)).count();
+        assert!(still_found < 5);
+        assert!(!filter.delete(&5000));
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let mut filter = CuckooFilter::<str>::new(10, 0.01);
+        filter.insert("test").unwrap();
+        filter.insert("test").unwrap();
+        assert!(filter.delete("test"));
+        assert!(filter.contains("test"));
+        assert!(filter.delete("test"));
+        assert!(!filter.contains("test"));
+    }
+
+    #[test]
+    fn test_sizing() {
+        let filter = CuckooFilter::<u64>::new(1000, 0.01);
+        assert!(filter.capacity() as f64 * MAX_LOAD >= 1000.0);
+        assert!(filter.capacity().is_power_of_two());
+        assert!(filter.fp_rate() <= 0.01);
+        assert_eq!(fingerprint_bits(0.01), 10);
+        assert_eq!(filter.slots.len(), filter.capacity() * 10);
+    }
+
+    #[test]
+    fn test_fp_rate() {
+        let mut filter = CuckooFilter::<u64>::new(10_000, 0.01);
+        for i in 0..10_000 {
+            filter.insert(&i).unwrap();
+        }
+        let false_positives = (10_000..210_000).filter(|i| filter.contains(i)).count();
+        assert!((false_positives as f64 / 200_000.0) < 0.01);
+    }
+
+    #[test]
+    fn test_full() {
+        let mut filter = CuckooFilter::<u64>::new(100, 0.01);
+        let mut inserted = 0;
+        let err = loop {
+            match filter.insert(&inserted) {
+                Ok(()) => inserted += 1,
+                Err(err) => break err,
+            }
+        };
+        assert_eq!(err, CuckooFilterError::Full);
+        assert!(filter.load_factor() > 0.85);
+        // Nothing inserted before the error was lost.
+        for i in 0..inserted {
+            assert!(filter.contains(&i));
+        }
+        // Deleting makes room again.
+        for i in 0..10 {
+            assert!(filter.delete(&i));
+        }
+        assert!(filter.insert(&inserted).is_ok());
+        assert!(filter.contains(&inserted));
+    }
+
+    #[test]
+    #[should_panic(expected = "expected items must be positive")]
+    fn test_zero_size_panics() {
+        CuckooFilter::<u64>::new(0, 0.01);
+    }
+
+    #[test]
+    #[should_panic(expected = "false positive rate must be between 0 and 1")]
+    fn test_bad_fp_rate_panics() {
+        CuckooFilter::<u64>::new(100, f64::NAN);
+    }
+}
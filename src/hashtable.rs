@@ -1,158 +1,467 @@
-#![allow(dead_code)]
-mod hashtable {
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
-    use std::vec;
-    #[derive(Debug, Default)]
-    pub struct HashMapEntry<K, V> {
-        pub key: K,
-        pub value: V,
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// A key and its value as stored in a [`HashMap`] slot.
+#[derive(Clone, Debug, Default)]
+pub struct HashMapEntry<K, V> {
+    pub key: K,
+    pub value: V,
+}
+
+impl<K, V> HashMapEntry<K, V> {
+    /// ```
+    /// use linked_list_1::hashtable::HashMapEntry;
+    ///
+    /// let entry = HashMapEntry::new("key", 1);
+    /// assert_eq!((entry.key, entry.value), ("key", 1));
+    /// ```
+    pub fn new(key: K, value: V) -> Self {
+        HashMapEntry { key, value }
     }
 
-    impl<K, V: Copy> HashMapEntry<K, V> {
-        pub fn new(key: K, value: V) -> Self {
-            HashMapEntry { key, value }
-        }
+    fn replace(&mut self, value: V) -> V {
+        std::mem::replace(&mut self.value, value)
+    }
+}
+
+// A slot of the table. Deleted leaves a tombstone behind so probe chains that
+// ran through the slot still reach the keys past it.
+#[derive(Debug)]
+enum Slot<K, V> {
+    Empty,
+    Occupied(HashMapEntry<K, V>),
+    Deleted,
+}
 
-        fn replace(&mut self, value: V) -> V {
-            let previous_value = self.value;
-            self.value = value;
-            previous_value
+impl<K, V> Slot<K, V> {
+    fn into_entry(self) -> Option<HashMapEntry<K, V>> {
+        match self {
+            Slot::Occupied(entry) => Some(entry),
+            _ => None,
         }
     }
+}
+
+/// The load factor a map grows at unless told otherwise.
+pub const DEFAULT_MAX_LOAD_FACTOR: f64 = 0.75;
+
+fn empty_slots<K, V>(size: usize) -> Vec<Slot<K, V>> {
+    (0..size).map(|_| Slot::Empty).collect()
+}
+
+/// How a [`HashMap`] picks the next slot after a collision. The map's size
+/// is always a power of two, and every strategy visits each slot within a
+/// bounded number of steps, so probes end even when no slot is empty.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ProbeStrategy {
+    /// `h, h + 1, h + 2, ...`
+    Linear,
+    /// `h, h + 1, h + 3, h + 6, ...`, stepping by the triangular numbers.
+    Triangular,
+    /// `h, h + s, h + 2s, ...` with an odd step `s` taken from the high bits
+    /// of the hash, so keys sharing a slot still part ways.
+    DoubleHashing,
+    /// CPython's dict probe: `i = 5i + 1 + perturb`, shifting five more bits
+    /// of the hash into `perturb` each step until it runs out.
+    #[default]
+    Perturbation,
+}
+
+// Bits of the hash shifted out of `perturb` per step, as in CPython.
+const PERTURB_SHIFT: u32 = 5;
+
+// The slots a key may occupy, in order. For a power-of-two `size`:
+//   - linear and double hashing step by an odd stride, which is coprime to
+//     the size and so reaches every slot within `size` steps,
+//   - the triangular numbers modulo a power of two are a permutation of it,
+//   - the perturbation recurrence becomes `5i + 1`, a full-period LCG modulo a
+//     power of two, once `perturb` is zero, which takes at most 13 steps.
+// Probes are cut off after that, so they end even with no empty slot left.
+struct Probe {
+    strategy: ProbeStrategy,
+    mask: usize,
+    index: usize,
+    step: usize,
+    perturb: u64,
+    remaining: usize,
+}
 
-    impl<K: Clone, V: Copy> Clone for HashMapEntry<K, V> {
-        fn clone(&self) -> Self {
-            HashMapEntry {
-                key: self.key.clone(),
-                value: self.value,
+impl Probe {
+    fn new(strategy: ProbeStrategy, hash: u64, size: usize) -> Probe {
+        debug_assert!(size == 0 || size.is_power_of_two());
+        let mask = size.wrapping_sub(1);
+        let (step, remaining) = match strategy {
+            ProbeStrategy::Linear | ProbeStrategy::Triangular => (1, size),
+            ProbeStrategy::DoubleHashing => ((hash >> 32) as usize | 1, size),
+            ProbeStrategy::Perturbation => {
+                let steps = u64::BITS.div_ceil(PERTURB_SHIFT) as usize;
+                (0, if size == 0 { 0 } else { size + steps })
             }
+        };
+        Probe {
+            strategy,
+            mask,
+            index: hash as usize & mask,
+            step,
+            perturb: hash,
+            remaining,
         }
     }
-    // HashMap will not handle collision.
-    // It will require a function f(V) -> V on how to handle the previous_value
-    #[derive(Debug)]
-    pub struct HashMap<K, V> {
-        amount: usize,
-        entries: Vec<Option<HashMapEntry<K, V>>>,
-    }
+}
+
+impl Iterator for Probe {
+    type Item = usize;
 
-    impl<K, V> HashMap<K, V>
-    where
-        K: PartialEq + Eq + Default + Hash + Clone,
-        V: Default + Copy,
-    {
-        pub fn new(size: usize) -> Self {
-            HashMap {
-                amount: 0,
-                entries: vec![None; size],
+    fn next(&mut self) -> Option<usize> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let index = self.index;
+        self.index = match self.strategy {
+            ProbeStrategy::Linear | ProbeStrategy::DoubleHashing => index.wrapping_add(self.step),
+            ProbeStrategy::Triangular => {
+                let next = index.wrapping_add(self.step);
+                self.step += 1;
+                next
+            }
+            ProbeStrategy::Perturbation => {
+                self.perturb >>= PERTURB_SHIFT;
+                index
+                    .wrapping_mul(5)
+                    .wrapping_add(1)
+                    .wrapping_add(self.perturb as usize)
             }
+        } & self.mask;
+        Some(index)
+    }
+}
+
+/// An open-addressing hash map. Colliding keys are probed into other slots
+/// of a single `Vec`, and the table at least doubles whenever an insert would
+/// push it past its maximum load factor.
+/// Deleted keys leave a tombstone that later inserts reuse; once tombstones
+/// take up a quarter of the table it is rebuilt without them. Sizes are
+/// rounded up to a power of two.
+///
+/// ```
+/// use linked_list_1::hashtable::HashMap;
+///
+/// let mut map = HashMap::new(16);
+/// map.insert("apples", vec![3]);
+/// map.insert("pears", vec![5]);
+/// assert_eq!(map.insert("apples", vec![4]), Some(vec![3]));
+/// map.get_mut(&"apples").unwrap().push(6);
+/// assert_eq!(map.get(&"apples"), Some(&vec![4, 6]));
+/// assert_eq!(map.amount(), 2);
+///
+/// assert_eq!(map.delete(&"pears"), Some(("pears", vec![5])));
+/// assert_eq!(map.get(&"pears"), None);
+/// ```
+#[derive(Debug)]
+pub struct HashMap<K, V> {
+    amount: usize,
+    tombstones: usize,
+    max_load_factor: f64,
+    probe_strategy: ProbeStrategy,
+    entries: Vec<Slot<K, V>>,
+}
+
+impl<K: Hash + Eq, V> HashMap<K, V> {
+    /// Creates a map with `size` slots, rounded up to a power of two.
+    ///
+    /// ```
+    /// use linked_list_1::hashtable::HashMap;
+    ///
+    /// assert_eq!(HashMap::<u32, u32>::new(10).size(), 16);
+    /// ```
+    pub fn new(size: usize) -> Self {
+        HashMap {
+            amount: 0,
+            tombstones: 0,
+            max_load_factor: DEFAULT_MAX_LOAD_FACTOR,
+            probe_strategy: ProbeStrategy::default(),
+            entries: empty_slots(Self::round_size(size)),
+        }
+    }
+
+    fn round_size(size: usize) -> usize {
+        if size == 0 {
+            0
+        } else {
+            size.next_power_of_two()
         }
+    }
 
-        pub fn size(&self) -> usize {
-            return self.entries.len();
+    /// Switches to probing with `strategy`, re-inserting every entry.
+    ///
+    /// ```
+    /// use linked_list_1::hashtable::{HashMap, ProbeStrategy};
+    ///
+    /// let mut map = HashMap::new(8);
+    /// map.insert("a", 1);
+    /// map.set_probe_strategy(ProbeStrategy::Linear);
+    /// assert_eq!(map.probe_strategy(), ProbeStrategy::Linear);
+    /// assert_eq!(map.get(&"a"), Some(&1));
+    /// ```
+    pub fn set_probe_strategy(&mut self, strategy: ProbeStrategy) {
+        if strategy != self.probe_strategy {
+            self.probe_strategy = strategy;
+            self.rehash(self.size());
         }
+    }
+
+    pub fn probe_strategy(&self) -> ProbeStrategy {
+        self.probe_strategy
+    }
 
-        pub fn amount(&self) -> usize {
-            self.amount
+    /// Creates a map that holds `capacity` entries without growing.
+    ///
+    /// ```
+    /// use linked_list_1::hashtable::HashMap;
+    ///
+    /// let mut map = HashMap::with_capacity(100);
+    /// let size = map.size();
+    /// for i in 0..100u32 {
+    ///     map.insert(i, i);
+    /// }
+    /// assert_eq!(map.size(), size);
+    /// ```
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut map = HashMap::new(0);
+        map.reserve(capacity);
+        map
+    }
+
+    /// The largest fraction of slots that may hold entries before the map
+    /// grows. Must be in (0, 1]; lowering it may grow the map right away.
+    pub fn set_max_load_factor(&mut self, max_load_factor: f64) {
+        assert!(
+            max_load_factor > 0.0 && max_load_factor <= 1.0,
+            "max load factor must be in (0, 1], got {}",
+            max_load_factor
+        );
+        self.max_load_factor = max_load_factor;
+        self.reserve(0);
+    }
+
+    pub fn max_load_factor(&self) -> f64 {
+        self.max_load_factor
+    }
+
+    /// The fraction of slots holding entries.
+    pub fn load_factor(&self) -> f64 {
+        if self.size() == 0 {
+            0.0
+        } else {
+            self.amount as f64 / self.size() as f64
         }
+    }
+
+    /// The number of entries the map holds before it next grows.
+    pub fn capacity(&self) -> usize {
+        // Round down, but never call a slot full when it isn't
+        ((self.size() as f64 * self.max_load_factor) as usize).min(self.size())
+    }
 
-        pub fn remaining_entries(&self) -> usize {
-            self.size() - self.amount()
+    // The slots needed to hold `amount` entries within the load factor.
+    fn slots_for(&self, amount: usize) -> usize {
+        let slots = (amount as f64 / self.max_load_factor).ceil() as usize;
+        // Guard against the float rounding the capacity below `amount`
+        if ((slots as f64 * self.max_load_factor) as usize) < amount {
+            slots + 1
+        } else {
+            slots
         }
+    }
 
-        #[inline]
-        pub fn hash_key(key: &K) -> u64 {
-            let mut hasher = DefaultHasher::new();
-            key.hash(&mut hasher);
-            hasher.finish()
+    /// Makes room for at least `additional` more entries without growing.
+    ///
+    /// ```
+    /// use linked_list_1::hashtable::HashMap;
+    ///
+    /// let mut map = HashMap::new(4);
+    /// map.insert("a", 1);
+    /// map.reserve(10);
+    /// assert!(map.capacity() >= 11);
+    /// assert_eq!(map.get(&"a"), Some(&1));
+    /// ```
+    pub fn reserve(&mut self, additional: usize) {
+        let needed = self.slots_for(self.amount + additional);
+        if needed > self.size() {
+            self.rehash(needed);
         }
+    }
 
-        #[inline]
-        pub fn get_index(&mut self, key: &K) -> usize {
-            (HashMap::<K, V>::hash_key(&key) as usize) % self.size()
+    /// Shrinks the map to the smallest power of two of slots that holds its
+    /// entries within the load factor.
+    pub fn shrink_to_fit(&mut self) {
+        let needed = Self::round_size(self.slots_for(self.amount));
+        if needed < self.size() {
+            self.rehash(needed);
         }
+    }
 
-        pub fn get(&mut self, key: &K) -> Option<V> {
-            let mut index = self.get_index(key);
-            loop {
-                match &self.entries[index] {
-                    None => return None, // Stop when we find an empty slot
-                    Some(entry) => {
-                        if entry.key == *key {
-                            return Some(entry.value);
-                        }
-                        // else roll the index
-                        index = ((5 * index) + 1) % self.size();
-                    }
-                }
+    /// The number of slots, used or not.
+    pub fn size(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The number of entries in the map.
+    pub fn amount(&self) -> usize {
+        self.amount
+    }
+
+    /// The number of free slots.
+    ///
+    /// ```
+    /// use linked_list_1::hashtable::HashMap;
+    ///
+    /// let mut map = HashMap::new(8);
+    /// map.insert(1u32, 'a');
+    /// assert_eq!((map.size(), map.amount(), map.remaining_entries()), (8, 1, 7));
+    /// ```
+    pub fn remaining_entries(&self) -> usize {
+        self.size() - self.amount()
+    }
+
+    #[inline]
+    fn hash_key(key: &K) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn probe(&self, key: &K) -> Probe {
+        Probe::new(
+            self.probe_strategy,
+            HashMap::<K, V>::hash_key(key),
+            self.size(),
+        )
+    }
+
+    // The slot holding `key`, if it's in the map.
+    fn find(&self, key: &K) -> Option<usize> {
+        for index in self.probe(key) {
+            match &self.entries[index] {
+                Slot::Empty => return None, // Stop when we find an empty slot
+                Slot::Occupied(entry) if entry.key == *key => return Some(index),
+                // Probe past tombstones and other keys
+                _ => continue,
             }
         }
+        None
+    }
 
-        pub fn insert(&mut self, key: K, value: V) {
-            // if there is no space left to insert, extend the size of the number of available entries in memory
-            if self.remaining_entries() == 0 {
-                self.extend(self.size());
-            }
-            let mut index = self.get_index(&key);
-            loop {
-                match &self.entries[index] {
-                    None => {
-                        self.entries[index] = Some(HashMapEntry::<K, V>::new(key.clone(), value));
-                        self.amount += 1;
-                        break;
-                    }
-                    Some(entry) => {
-                        if entry.key == key {
-                            self.entries[index] =
-                                Some(HashMapEntry::<K, V>::new(key.clone(), value));
-                            break;
-                        } else {
-                            index = ((5 * index) + 1) % self.size();
-                        }
-                    }
-                }
-            }
+    /// Returns the value stored for `key`, if any.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        match &self.entries[self.find(key)?] {
+            Slot::Occupied(entry) => Some(&entry.value),
+            _ => None,
         }
+    }
 
-        pub fn delete(&mut self, key: &K) -> Option<HashMapEntry<K, V>> {
-            let mut index = self.get_index(key);
-            loop {
-                match &mut self.entries[index] {
-                    None => return None, // Stop when we find an empty slot
-                    Some(entry) => {
-                        if entry.key == *key {
-                            let popped_entry = Some(entry.clone());
-                            self.entries[index] = None;
-                            self.amount -= 1;
-                            return popped_entry;
-                        }
-                        // Update the index
-                        index = ((5 * index) + 1) % self.size();
-                    }
-                }
-            }
+    /// Returns the value stored for `key` to change in place, if any.
+    ///
+    /// ```
+    /// use linked_list_1::hashtable::HashMap;
+    ///
+    /// let mut map = HashMap::new(8);
+    /// map.insert("count", 1);
+    /// *map.get_mut(&"count").unwrap() += 1;
+    /// assert_eq!(map.get(&"count"), Some(&2));
+    /// assert_eq!(map.get_mut(&"missing"), None);
+    /// ```
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let index = self.find(key)?;
+        match &mut self.entries[index] {
+            Slot::Occupied(entry) => Some(&mut entry.value),
+            _ => None,
         }
-        // Extends the capacity of the hashmap with a new value;
-        pub fn extend(&mut self, new_size: usize) {
-            let mut new_entries = vec![None; self.size() + new_size];
-            for entry in self.entries.iter() {
-                match entry {
-                    None => continue,
-                    Some(entry) => {
-                        let new_index =
-                            HashMap::<K, V>::hash_key(&entry.key) as usize % new_entries.len();
-                        new_entries[new_index] = Some(entry.clone());
-                    }
+    }
+
+    /// Stores `value` for `key`, returning the value it replaced, if any.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        // Grow before the insert would pass the load factor. It could be an
+        // update of a key already there, but that's rare next to the check.
+        if self.amount >= self.capacity() {
+            let size = self.slots_for(self.amount + 1).max(self.size() * 2);
+            self.rehash(size);
+        } else if self.tombstones > self.size() / 4 {
+            self.rehash(self.size());
+        }
+        // The first tombstone on the way is reused, but the probe has to
+        // go on to the first empty slot in case the key is further along.
+        let mut free = None;
+        for index in self.probe(&key) {
+            match &mut self.entries[index] {
+                Slot::Empty => {
+                    free.get_or_insert(index);
+                    break;
+                }
+                Slot::Deleted => {
+                    free.get_or_insert(index);
                 }
+                Slot::Occupied(entry) if entry.key == key => {
+                    return Some(entry.replace(value));
+                }
+                Slot::Occupied(_) => {}
             }
-            self.entries = new_entries;
         }
+        // The load factor keeps a slot that isn't occupied, and the probe
+        // visits every slot
+        let index = free.expect("probe should reach a free slot");
+        if let Slot::Deleted = self.entries[index] {
+            self.tombstones -= 1;
+        }
+        self.entries[index] = Slot::Occupied(HashMapEntry::new(key, value));
+        self.amount += 1;
+        None
+    }
+
+    /// Removes `key` from the map, returning the stored key and value if it
+    /// was present.
+    pub fn delete(&mut self, key: &K) -> Option<(K, V)> {
+        let index = self.find(key)?;
+        let entry = std::mem::replace(&mut self.entries[index], Slot::Deleted).into_entry()?;
+        self.amount -= 1;
+        self.tombstones += 1;
+        Some((entry.key, entry.value))
+    }
+
+    /// Grows the map by `new_size` slots, re-inserting every entry. The new
+    /// size is rounded up to a power of two.
+    ///
+    /// ```
+    /// use linked_list_1::hashtable::HashMap;
+    ///
+    /// let mut map = HashMap::<u32, u32>::new(4);
+    /// map.extend(10);
+    /// assert_eq!(map.size(), 16);
+    /// ```
+    pub fn extend(&mut self, new_size: usize) {
+        self.rehash(self.size() + new_size);
+    }
+
+    // Moves every entry into a fresh table of at least `size` slots,
+    // dropping the tombstones.
+    fn rehash(&mut self, size: usize) {
+        let size = Self::round_size(size.max(self.amount));
+        let old = std::mem::replace(&mut self.entries, empty_slots(size));
+        for entry in old.into_iter().filter_map(Slot::into_entry) {
+            let hash = HashMap::<K, V>::hash_key(&entry.key);
+            let index = Probe::new(self.probe_strategy, hash, size)
+                .find(|&index| matches!(self.entries[index], Slot::Empty))
+                .expect("probe should reach a free slot");
+            self.entries[index] = Slot::Occupied(entry);
+        }
+        self.tombstones = 0;
     }
 }
+
 #[cfg(test)]
 mod tests {
-    use super::hashtable::*;
+    use super::*;
     #[test]
     fn build_hash_map_entry() {
         let entry = HashMapEntry::new("Hello", 0);
@@ -166,28 +475,24 @@ mod tests {
     fn build_hash_map() {
         let size = 10;
         let mut hash_map = HashMap::<&str, i32>::new(size);
-        assert_eq!(hash_map.size(), size);
+        // sizes are rounded up to a power of two
+        assert_eq!(hash_map.size(), 16);
         hash_map.extend(size * 2);
-        // extended the size by twice so we have size + 2*size = 3*size
-        assert!(hash_map.size() == size * 3);
-    }
-
-    fn add_value(prev_value: usize, value: usize) -> usize {
-        let new_value = prev_value + value;
-        new_value
+        // 16 + 20 slots, rounded up
+        assert!(hash_map.size() == 64);
     }
 
     #[test]
     fn test_amount() {
         let size = 32;
         let mut hash_map = HashMap::<&str, usize>::new(size);
-        let words = vec!["Hello", "World", "the", "hash", "table", "can", "word"];
+        let words = ["Hello", "World", "the", "hash", "table", "can", "word"];
         for w in words.iter() {
             hash_map.insert(w, 1);
         }
-        hash_map.insert(&"World", 1);
-        hash_map.insert(&"World", 1);
-        hash_map.insert(&"the", 1);
+        hash_map.insert("World", 1);
+        hash_map.insert("World", 1);
+        hash_map.insert("the", 1);
         assert_eq!(hash_map.amount(), words.len());
     }
     #[test]
@@ -198,12 +503,12 @@ mod tests {
         for w in words {
             hash_map.insert(w, 1);
         }
-        hash_map.insert(&"World", 2);
-        hash_map.insert(&"World", 3);
-        hash_map.insert(&"the", 2);
-        assert_eq!(hash_map.get(&"World"), Some(3));
-        assert_eq!(hash_map.get(&"the"), Some(2));
-        assert_eq!(hash_map.get(&"can"), Some(1));
+        hash_map.insert("World", 2);
+        hash_map.insert("World", 3);
+        hash_map.insert("the", 2);
+        assert_eq!(hash_map.get(&"World"), Some(&3));
+        assert_eq!(hash_map.get(&"the"), Some(&2));
+        assert_eq!(hash_map.get(&"can"), Some(&1));
     }
 
     #[test]
@@ -214,10 +519,220 @@ mod tests {
         for w in words {
             hash_map.insert(w, 1);
         }
-        hash_map.insert(&"World", 1);
-        hash_map.insert(&"World", 2);
-        hash_map.insert(&"the", 1);
+        hash_map.insert("World", 1);
+        hash_map.insert("World", 2);
+        hash_map.insert("the", 1);
         hash_map.delete(&"World");
         assert_eq!(hash_map.get(&"World"), None);
     }
+
+    // Keys that all start probing at the same slot of a map with `size` slots.
+    fn colliding_keys(size: usize, count: usize) -> Vec<u64> {
+        let slot = |key: &u64| HashMap::<u64, u64>::hash_key(key) as usize & (size - 1);
+        let target = slot(&0);
+        (0..)
+            .filter(|key| slot(key) == target)
+            .take(count)
+            .collect()
+    }
+
+    #[test]
+    fn test_delete_keeps_probe_chain() {
+        let keys = colliding_keys(64, 3);
+        let mut hash_map = HashMap::new(64);
+        for &key in &keys {
+            hash_map.insert(key, key + 1);
+        }
+        hash_map.delete(&keys[0]);
+        assert_eq!(hash_map.get(&keys[0]), None);
+        assert_eq!(hash_map.get(&keys[1]), Some(&(keys[1] + 1)));
+        assert_eq!(hash_map.get(&keys[2]), Some(&(keys[2] + 1)));
+        // A deleted key isn't found again, and the chain behind it is intact
+        assert!(hash_map.delete(&keys[0]).is_none());
+        assert_eq!(hash_map.delete(&keys[2]).unwrap().1, keys[2] + 1);
+        assert_eq!(hash_map.get(&keys[1]), Some(&(keys[1] + 1)));
+    }
+
+    #[test]
+    fn test_insert_reuses_tombstone() {
+        let keys = colliding_keys(64, 2);
+        let mut hash_map = HashMap::new(64);
+        hash_map.insert(keys[0], 1);
+        hash_map.insert(keys[1], 2);
+        hash_map.delete(&keys[0]);
+        assert_eq!(hash_map.tombstones, 1);
+        // Updating a key past the tombstone mustn't add a second copy of it
+        hash_map.insert(keys[1], 3);
+        assert_eq!((hash_map.amount(), hash_map.tombstones), (1, 1));
+        hash_map.insert(keys[0], 4);
+        assert_eq!((hash_map.amount(), hash_map.tombstones), (2, 0));
+        assert_eq!(hash_map.get(&keys[0]), Some(&4));
+        assert_eq!(hash_map.get(&keys[1]), Some(&3));
+    }
+
+    #[test]
+    fn test_tombstones_trigger_rehash() {
+        let mut hash_map = HashMap::new(64);
+        for round in 0..100u64 {
+            for key in round * 10..round * 10 + 10 {
+                hash_map.insert(key, key);
+            }
+            for key in round * 10..round * 10 + 10 {
+                hash_map.delete(&key);
+            }
+            assert!(hash_map.tombstones <= 16 + 10);
+        }
+        assert_eq!(hash_map.size(), 64);
+        assert_eq!(hash_map.amount(), 0);
+        assert!((0..1000).all(|key| hash_map.get(&key).is_none()));
+        // Rebuilt once the tombstones pass a quarter of the slots
+        for key in 0..17 {
+            hash_map.insert(key, key);
+        }
+        for key in 0..17 {
+            hash_map.delete(&key);
+        }
+        assert!(hash_map.tombstones > 16);
+        hash_map.insert(7, 7);
+        assert_eq!(hash_map.tombstones, 0);
+        assert_eq!(hash_map.get(&7), Some(&7));
+    }
+
+    #[test]
+    fn test_extend_keeps_colliding_entries() {
+        let keys = colliding_keys(16, 8);
+        let mut hash_map = HashMap::new(16);
+        hash_map.set_max_load_factor(1.0);
+        for &key in &keys {
+            hash_map.insert(key, key * 2);
+        }
+        hash_map.extend(16);
+        assert_eq!(hash_map.size(), 32);
+        assert_eq!(hash_map.amount(), keys.len());
+        for &key in &keys {
+            assert_eq!(hash_map.get(&key), Some(&(key * 2)));
+        }
+    }
+
+    #[test]
+    fn test_grows_at_max_load_factor() {
+        let mut hash_map = HashMap::new(8);
+        for key in 0..6u32 {
+            hash_map.insert(key, key);
+        }
+        assert_eq!(hash_map.size(), 8);
+        hash_map.insert(6, 6);
+        assert_eq!(hash_map.size(), 16);
+        assert!(hash_map.load_factor() <= DEFAULT_MAX_LOAD_FACTOR);
+
+        let mut hash_map = HashMap::new(10);
+        hash_map.set_max_load_factor(0.5);
+        for key in 0..1000u32 {
+            hash_map.insert(key, key);
+            assert!(hash_map.load_factor() <= 0.5);
+        }
+        assert!((0..1000).all(|key| hash_map.get(&key) == Some(&key)));
+        // Lowering the limit grows the map straight away
+        hash_map.set_max_load_factor(0.25);
+        assert!(hash_map.load_factor() <= 0.25);
+        assert!((0..1000).all(|key| hash_map.get(&key) == Some(&key)));
+    }
+
+    #[test]
+    #[should_panic(expected = "max load factor must be in (0, 1]")]
+    fn test_rejects_bad_load_factor() {
+        HashMap::<u32, u32>::new(8).set_max_load_factor(1.5);
+    }
+
+    #[test]
+    fn test_reserve_and_shrink() {
+        for capacity in [0, 1, 3, 10, 1000] {
+            let hash_map = HashMap::<u32, u32>::with_capacity(capacity);
+            assert!(hash_map.capacity() >= capacity);
+            let slots = (capacity * 4).div_ceil(3);
+            assert!(hash_map.size() <= (slots + 1).next_power_of_two());
+        }
+
+        let mut hash_map = HashMap::with_capacity(10);
+        hash_map.insert(1u32, 1u32);
+        hash_map.reserve(500);
+        let size = hash_map.size();
+        assert!(hash_map.capacity() >= 501);
+        for key in 2..=501 {
+            hash_map.insert(key, key);
+        }
+        assert_eq!(hash_map.size(), size);
+
+        for key in 11..=501 {
+            hash_map.delete(&key);
+        }
+        hash_map.shrink_to_fit();
+        // 14 slots, rounded up
+        assert_eq!(hash_map.size(), 16);
+        assert_eq!(hash_map.tombstones, 0);
+        assert!((1..=10).all(|key| hash_map.get(&key) == Some(&key)));
+        assert!((11..=501).all(|key| hash_map.get(&key).is_none()));
+    }
+
+    const STRATEGIES: [ProbeStrategy; 4] = [
+        ProbeStrategy::Linear,
+        ProbeStrategy::Triangular,
+        ProbeStrategy::DoubleHashing,
+        ProbeStrategy::Perturbation,
+    ];
+
+    #[test]
+    fn test_probes_visit_every_slot() {
+        let mut hash: u64 = 0x2545_f491_4f6c_dd1d;
+        for strategy in STRATEGIES {
+            for bits in 0..=10 {
+                let size = 1 << bits;
+                for _ in 0..100 {
+                    hash ^= hash << 13;
+                    hash ^= hash >> 7;
+                    hash ^= hash << 17;
+                    let probe: Vec<usize> = Probe::new(strategy, hash, size).collect();
+                    assert!(probe.len() <= size + 13);
+                    let mut seen = vec![false; size];
+                    probe.iter().for_each(|&index| seen[index] = true);
+                    assert!(
+                        seen.iter().all(|&seen| seen),
+                        "{:?} missed a slot of {}",
+                        strategy,
+                        size
+                    );
+                }
+            }
+        }
+        assert_eq!(Probe::new(ProbeStrategy::Perturbation, 1, 0).count(), 0);
+    }
+
+    #[test]
+    fn test_lookups_terminate_without_empty_slots() {
+        for strategy in STRATEGIES {
+            for size in [1, 2, 10, 30, 64] {
+                let mut hash_map = HashMap::new(size);
+                hash_map.set_probe_strategy(strategy);
+                hash_map.set_max_load_factor(1.0);
+                let size = hash_map.size() as u64;
+                // Every slot occupied
+                for key in 0..size {
+                    hash_map.insert(key, key);
+                }
+                assert_eq!(hash_map.remaining_entries(), 0);
+                assert_eq!(hash_map.get(&size), None);
+                assert!(hash_map.delete(&size).is_none());
+                assert!((0..size).all(|key| hash_map.get(&key) == Some(&key)));
+                // Every slot a tombstone
+                for key in 0..size {
+                    hash_map.delete(&key);
+                }
+                assert_eq!(hash_map.tombstones as u64, size);
+                assert_eq!(hash_map.get(&0), None);
+                assert!(hash_map.delete(&size).is_none());
+                hash_map.insert(size, 1);
+                assert_eq!(hash_map.get(&size), Some(&1));
+            }
+        }
+    }
 }
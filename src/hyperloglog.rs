@@ -0,0 +1,705 @@
+use crate::bloom_filter::KeyBytes;
+use crate::bloom_hasher::double_hash;
+use crate::serialization::{from_slice, read_array, read_bytes, DecodeError};
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+
+// Saved sketches use the crate's usual 32 byte header:
+//
+//   offset  size  field
+//        0     4  magic "HLLS"
+//        4     2  format version, little endian
+//        6     1  precision
+//        7     1  encoding, 0 for dense and 1 for sparse
+//        8     8  number of payload entries, little endian
+//       16     8  reserved, zero
+//       24     4  crc32 of bytes 0..24 and the payload
+//       28     4  reserved, zero
+//       32     -  dense: one byte per register
+//                 sparse: the sorted entries as little endian u32
+pub const MAGIC: [u8; 4] = *b"HLLS";
+pub const VERSION: u16 = 1;
+pub const HEADER_LEN: usize = 32;
+
+pub const MIN_PRECISION: u8 = 4;
+pub const MAX_PRECISION: u8 = 18;
+
+// Index bits used while the sketch is sparse. Far more registers than any
+// dense precision, so small cardinalities are counted almost exactly.
+const SPARSE_PRECISION: u32 = 25;
+const DENSE: u8 = 0;
+const SPARSE: u8 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IncompatiblePrecision(pub u8, pub u8);
+
+impl fmt::Display for IncompatiblePrecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sketches have different precisions: {} and {}",
+            self.0, self.1
+        )
+    }
+}
+
+impl Error for IncompatiblePrecision {}
+
+// The register index of a hash at precision p and its rank: one more than
+// the number of leading zeros in the remaining 64 - p bits.
+#[inline]
+fn index_and_rank(hash: u64, p: u32) -> (usize, u8) {
+    let index = (hash >> (64 - p)) as usize;
+    let rank = ((hash << p).leading_zeros() + 1).min(64 - p + 1);
+    (index, rank as u8)
+}
+
+// A sparse entry packs a SPARSE_PRECISION bit index above a 6 bit rank, so
+// sorting entries sorts them by index.
+#[inline]
+fn sparse_entry(index: usize, rank: u8) -> u32 {
+    (index as u32) << 6 | rank as u32
+}
+
+#[inline]
+fn sparse_index(entry: u32) -> usize {
+    (entry >> 6) as usize
+}
+
+#[inline]
+fn sparse_rank(entry: u32) -> u8 {
+    (entry & 0x3f) as u8
+}
+
+// The dense register and rank a sparse entry stands for at precision p. The
+// index bits past the first p belong to the dense rank: when any of them is
+// set they decide it, otherwise the sparse rank continues after them.
+fn sparse_to_dense(entry: u32, p: u32) -> (usize, u8) {
+    let extra = SPARSE_PRECISION - p;
+    let index = sparse_index(entry);
+    let low = (index & ((1 << extra) - 1)) as u32;
+    let rank = if low != 0 {
+        low.leading_zeros() - (32 - extra) + 1
+    } else {
+        extra + sparse_rank(entry) as u32
+    };
+    (index >> extra, rank as u8)
+}
+
+// Sorts buffered sparse entries and keeps the highest rank per index.
+fn sorted_entries(mut entries: Vec<u32>) -> Vec<u32> {
+    // Sorting whole entries orders by index, then by rank.
+    entries.sort_unstable();
+    entries.dedup_by(|next, kept| {
+        let same = sparse_index(*next) == sparse_index(*kept);
+        if same {
+            *kept = *next;
+        }
+        same
+    });
+    entries
+}
+
+// Merges two sorted sparse lists, keeping the highest rank per index.
+fn merge_sparse(entries: &[u32], others: &[u32]) -> Vec<u32> {
+    let mut merged = Vec::with_capacity(entries.len() + others.len());
+    let (mut i, mut j) = (0, 0);
+    while i < entries.len() && j < others.len() {
+        let (a, b) = (entries[i], others[j]);
+        match sparse_index(a).cmp(&sparse_index(b)) {
+            std::cmp::Ordering::Less => {
+                merged.push(a);
+                i += 1;
+            }
+            std::cmp::Ordering::Greater => {
+                merged.push(b);
+                j += 1;
+            }
+            std::cmp::Ordering::Equal => {
+                merged.push(a.max(b));
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    merged.extend_from_slice(&entries[i..]);
+    merged.extend_from_slice(&others[j..]);
+    merged
+}
+
+// sigma and tau from Ertl, "New cardinality estimation algorithms for
+// HyperLogLog sketches", as series summed until they stop changing.
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if z == previous {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if z == previous {
+            return z / 3.0;
+        }
+    }
+}
+
+// Ertl's improved estimator over the histogram of register values of m
+// registers that each hold a rank in 0..=q + 1. It corrects the bias of the
+// raw HyperLogLog estimate at both ends of the range without the empirical
+// tables of HyperLogLog++.
+fn estimate(histogram: &[u64], m: f64) -> f64 {
+    let q = histogram.len() - 2;
+    let mut z = m * tau(1.0 - histogram[q + 1] as f64 / m);
+    for k in (1..=q).rev() {
+        z = 0.5 * (z + histogram[k] as f64);
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+    m * m / (2.0 * 2f64.ln() * z)
+}
+
+#[derive(Clone)]
+enum Registers {
+    // Sorted entries, one per sparse index that has been hit.
+    Sparse(Vec<u32>),
+    // One byte per register.
+    Dense(Vec<u8>),
+}
+
+// HyperLogLog estimates the number of distinct keys it has seen in 2^p
+// registers, with a relative standard error of about 1.04 / sqrt(2^p). Each
+// key's 64 bit murmur3 hash picks a register with its first p bits, which
+// keeps the longest run of leading zeros seen in the rest.
+//
+// A new sketch starts sparse: it only stores the registers that were hit, at
+// a precision of 25 bits, which is both smaller and far more accurate while
+// few keys have been seen. It switches to the dense registers once the
+// sparse entries would take more room than they do. As in HLL++, new sparse
+// entries go to an unsorted buffer that is sorted and merged into the list in
+// batches, so filling the sparse list costs linear time rather than an
+// insertion into the sorted list per key.
+pub struct HyperLogLog<T: ?Sized> {
+    registers: Registers,
+    // Sparse entries not yet merged into the sorted list, empty when dense.
+    buffer: Vec<u32>,
+    precision: u8,
+    _key: PhantomData<fn(&T)>,
+}
+
+impl<T: ?Sized> HyperLogLog<T> {
+    // precision must be between MIN_PRECISION and MAX_PRECISION.
+    pub fn new(precision: u8) -> HyperLogLog<T> {
+        assert!(
+            (MIN_PRECISION..=MAX_PRECISION).contains(&precision),
+            "precision must be between {} and {}",
+            MIN_PRECISION,
+            MAX_PRECISION
+        );
+        HyperLogLog {
+            registers: Registers::Sparse(Vec::new()),
+            buffer: Vec::new(),
+            precision,
+            _key: PhantomData,
+        }
+    }
+
+    #[inline]
+    fn m(&self) -> usize {
+        1 << self.precision
+    }
+
+    // Sparse entries take 4 bytes, dense registers 1.
+    fn sparse_limit(&self) -> usize {
+        self.m() / 4
+    }
+
+    // Buffered entries kept before a merge. An eighth of the sparse limit
+    // keeps the buffer small while each merge's pass over the sorted list is
+    // paid for by that many inserts.
+    fn buffer_limit(&self) -> usize {
+        (self.sparse_limit() / 8).max(1)
+    }
+
+    fn to_dense(&self) -> Vec<u8> {
+        match &self.registers {
+            Registers::Dense(registers) => registers.clone(),
+            Registers::Sparse(entries) => {
+                let mut registers = vec![0; self.m()];
+                for &entry in entries.iter().chain(&self.buffer) {
+                    let (index, rank) = sparse_to_dense(entry, self.precision as u32);
+                    registers[index] = registers[index].max(rank);
+                }
+                registers
+            }
+        }
+    }
+
+    // The sorted sparse entries with the buffer merged in.
+    fn sparse_entries<'a>(&self, entries: &'a [u32]) -> Cow<'a, [u32]> {
+        if self.buffer.is_empty() {
+            Cow::Borrowed(entries)
+        } else {
+            Cow::Owned(merge_sparse(entries, &sorted_entries(self.buffer.clone())))
+        }
+    }
+
+    // Merges the buffer into the sorted entries, switching to dense registers
+    // if there are too many of them.
+    fn flush_buffer(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let buffer = sorted_entries(std::mem::take(&mut self.buffer));
+        if let Registers::Sparse(entries) = &mut self.registers {
+            *entries = merge_sparse(entries, &buffer);
+            if entries.len() > self.sparse_limit() {
+                self.registers = Registers::Dense(self.to_dense());
+            }
+        }
+    }
+
+    fn insert_hash(&mut self, hash: u64) {
+        match &mut self.registers {
+            Registers::Dense(registers) => {
+                let (index, rank) = index_and_rank(hash, self.precision as u32);
+                registers[index] = registers[index].max(rank);
+            }
+            Registers::Sparse(_) => {
+                let (index, rank) = index_and_rank(hash, SPARSE_PRECISION);
+                self.buffer.push(sparse_entry(index, rank));
+                if self.buffer.len() >= self.buffer_limit() {
+                    self.flush_buffer();
+                }
+            }
+        }
+    }
+
+    pub fn insert_bytes(&mut self, value: &[u8]) {
+        let (hash, _) = double_hash(value);
+        self.insert_hash(hash);
+    }
+
+    // Estimated number of distinct keys inserted.
+    pub fn estimate(&self) -> u64 {
+        let estimate = match &self.registers {
+            Registers::Sparse(entries) => {
+                let entries = self.sparse_entries(entries);
+                let m = 1u64 << SPARSE_PRECISION;
+                let mut histogram = vec![0; (64 - SPARSE_PRECISION) as usize + 2];
+                histogram[0] = m - entries.len() as u64;
+                for &entry in entries.iter() {
+                    histogram[sparse_rank(entry) as usize] += 1;
+                }
+                estimate(&histogram, m as f64)
+            }
+            Registers::Dense(registers) => {
+                let mut histogram = vec![0; 64 - self.precision as usize + 2];
+                for &register in registers {
+                    histogram[register as usize] += 1;
+                }
+                estimate(&histogram, self.m() as f64)
+            }
+        };
+        estimate.round() as u64
+    }
+
+    // Adds every key of `other` to this sketch: the result is the sketch of
+    // the union of both key sets.
+    pub fn merge(&mut self, other: &HyperLogLog<T>) -> Result<(), IncompatiblePrecision> {
+        if self.precision != other.precision {
+            return Err(IncompatiblePrecision(self.precision, other.precision));
+        }
+        self.flush_buffer();
+        match (&mut self.registers, &other.registers) {
+            (Registers::Sparse(entries), Registers::Sparse(others)) => {
+                *entries = merge_sparse(entries, &other.sparse_entries(others));
+                if entries.len() > self.sparse_limit() {
+                    self.registers = Registers::Dense(self.to_dense());
+                }
+            }
+            _ => {
+                let mut registers = self.to_dense();
+                for (register, other) in registers.iter_mut().zip(other.to_dense()) {
+                    *register = (*register).max(other);
+                }
+                self.registers = Registers::Dense(registers);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn precision(&self) -> u8 {
+        self.precision
+    }
+
+    pub fn is_sparse(&self) -> bool {
+        matches!(self.registers, Registers::Sparse(_))
+    }
+
+    // Relative standard error of the estimate once the sketch is dense.
+    pub fn relative_error(&self) -> f64 {
+        1.04 / (self.m() as f64).sqrt()
+    }
+
+    fn payload(&self) -> (u8, usize, Vec<u8>) {
+        match &self.registers {
+            Registers::Dense(registers) => (DENSE, registers.len(), registers.clone()),
+            Registers::Sparse(entries) => {
+                let entries = self.sparse_entries(entries);
+                // The buffer may hold what would have made the sketch dense
+                if entries.len() > self.sparse_limit() {
+                    return (DENSE, self.m(), self.to_dense());
+                }
+                (
+                    SPARSE,
+                    entries.len(),
+                    entries
+                        .iter()
+                        .flat_map(|entry| entry.to_le_bytes())
+                        .collect(),
+                )
+            }
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write_to(&mut bytes)
+            .expect("writing to a Vec never fails");
+        bytes
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let (encoding, len, payload) = self.payload();
+        let mut header = [0; HEADER_LEN];
+        header[0..4].copy_from_slice(&MAGIC);
+        header[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        header[6] = self.precision;
+        header[7] = encoding;
+        header[8..16].copy_from_slice(&(len as u64).to_le_bytes());
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(&header[..24]);
+        crc.update(&payload);
+        header[24..28].copy_from_slice(&crc.finalize().to_le_bytes());
+        writer.write_all(&header)?;
+        writer.write_all(&payload)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<HyperLogLog<T>, DecodeError> {
+        from_slice(bytes, |reader| HyperLogLog::read_from(reader))
+    }
+
+    // Reads exactly one sketch, leaving anything after it in the reader.
+    pub fn read_from<R: Read>(mut reader: R) -> Result<HyperLogLog<T>, DecodeError> {
+        let header: [u8; HEADER_LEN] = read_array(&mut reader)?;
+        if header[0..4] != MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version != VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        if header[16..24] != [0; 8] || header[28..32] != [0; 4] {
+            return Err(DecodeError::Corrupt("reserved header bytes are not zero"));
+        }
+        let precision = header[6];
+        if !(MIN_PRECISION..=MAX_PRECISION).contains(&precision) {
+            return Err(DecodeError::Corrupt("precision out of range"));
+        }
+        let mut sketch = HyperLogLog::new(precision);
+        let len = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        let byte_len = match header[7] {
+            DENSE if len == sketch.m() as u64 => sketch.m(),
+            SPARSE if len <= sketch.sparse_limit() as u64 => len as usize * 4,
+            DENSE | SPARSE => return Err(DecodeError::Corrupt("invalid register count")),
+            _ => return Err(DecodeError::Corrupt("unknown register encoding")),
+        };
+        let expected = u32::from_le_bytes(header[24..28].try_into().unwrap());
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(&header[..24]);
+        let payload = read_bytes(&mut reader, byte_len, &mut crc)?;
+        let found = crc.finalize();
+        if found != expected {
+            return Err(DecodeError::ChecksumMismatch { expected, found });
+        }
+        if header[7] == DENSE {
+            let max_rank = 64 - precision + 1;
+            if payload.iter().any(|&register| register > max_rank) {
+                return Err(DecodeError::Corrupt("register value out of range"));
+            }
+            sketch.registers = Registers::Dense(payload);
+        } else {
+            let max_rank = (64 - SPARSE_PRECISION + 1) as u8;
+            let entries: Vec<u32> = payload
+                .chunks_exact(4)
+                .map(|entry| u32::from_le_bytes(entry.try_into().unwrap()))
+                .collect();
+            let sorted = entries
+                .windows(2)
+                .all(|pair| sparse_index(pair[0]) < sparse_index(pair[1]));
+            let ranks_valid = entries
+                .iter()
+                .all(|&entry| (1..=max_rank).contains(&sparse_rank(entry)));
+            if !sorted || !ranks_valid || entries.iter().any(|&entry| entry >> 31 != 0) {
+                return Err(DecodeError::Corrupt("invalid sparse entries"));
+            }
+            sketch.registers = Registers::Sparse(entries);
+        }
+        Ok(sketch)
+    }
+}
+
+impl<T: Hash + ?Sized> HyperLogLog<T> {
+    pub fn insert(&mut self, value: &T) {
+        self.insert_bytes(&KeyBytes::of(value));
+    }
+}
+
+impl<T: ?Sized> Clone for HyperLogLog<T> {
+    fn clone(&self) -> Self {
+        HyperLogLog {
+            registers: self.registers.clone(),
+            buffer: self.buffer.clone(),
+            precision: self.precision,
+            _key: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashtable::HashMap;
+
+    // `events` keys drawn with repeats from 0..range, and the exact number
+    // of distinct ones among them.
+    fn stream(events: u64, range: u64) -> (Vec<u64>, usize) {
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let keys: Vec<u64> = (0..events)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state % range
+            })
+            .collect();
+        let mut distinct = HashMap::<u64, ()>::with_capacity(range as usize);
+        for &key in &keys {
+            distinct.insert(key, ());
+        }
+        let exact = distinct.amount();
+        (keys, exact)
+    }
+
+    fn relative_error(estimate: u64, exact: usize) -> f64 {
+        (estimate as f64 - exact as f64).abs() / exact as f64
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut hll = HyperLogLog::new(14);
+        assert_eq!(hll.estimate(), 0);
+        hll.insert("test");
+        hll.insert("test");
+        hll.insert("foo");
+        assert_eq!(hll.estimate(), 2);
+        assert!(hll.is_sparse());
+    }
+
+    #[test]
+    fn test_error_bounds() {
+        for precision in [10, 14] {
+            for range in [100, 1000, 10_000, 100_000] {
+                let (keys, exact) = stream(range * 2, range);
+                let mut hll = HyperLogLog::<u64>::new(precision);
+                for key in &keys {
+                    hll.insert(key);
+                }
+                let error = relative_error(hll.estimate(), exact);
+                // Three standard errors, far tighter while still sparse.
+                let bound = if hll.is_sparse() {
+                    0.01
+                } else {
+                    3.0 * hll.relative_error()
+                };
+                assert!(
+                    error < bound,
+                    "precision {} exact {} estimate {}",
+                    precision,
+                    exact,
+                    hll.estimate()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_sparse_to_dense() {
+        let mut sparse = HyperLogLog::<u64>::new(8);
+        let mut dense = HyperLogLog::<u64>::new(8);
+        dense.registers = Registers::Dense(vec![0; 256]);
+        let mut i = 0;
+        while sparse.is_sparse() {
+            sparse.insert(&i);
+            dense.insert(&i);
+            // Converting the sparse registers gives exactly the dense ones.
+            assert_eq!(sparse.to_dense(), dense.to_dense());
+            i += 1;
+        }
+        // Checked when the buffer of 8 entries is merged past 64 entries.
+        assert_eq!(i, 72);
+        assert_eq!(sparse.to_dense(), dense.to_dense());
+        for i in 72..10_000 {
+            sparse.insert(&i);
+            dense.insert(&i);
+        }
+        assert_eq!(sparse.to_dense(), dense.to_dense());
+    }
+
+    #[test]
+    fn test_buffered_entries() {
+        // 100 keys at precision 10 leave 4 entries in the buffer of 32.
+        let mut hll = HyperLogLog::<u64>::new(10);
+        for i in 0..100 {
+            hll.insert(&i);
+            hll.insert(&i);
+        }
+        assert!(!hll.buffer.is_empty());
+        assert_eq!(hll.estimate(), 100);
+
+        let mut flushed = hll.clone();
+        flushed.flush_buffer();
+        assert!(flushed.buffer.is_empty());
+        assert_eq!(flushed.to_dense(), hll.to_dense());
+        assert_eq!(flushed.to_bytes(), hll.to_bytes());
+
+        let mut merged = HyperLogLog::<u64>::new(10);
+        merged.merge(&hll).unwrap();
+        assert!(merged.is_sparse());
+        assert_eq!(merged.to_bytes(), flushed.to_bytes());
+    }
+
+    #[test]
+    fn test_sparse_inserts_are_batched() {
+        // Filling the 65536 sparse entries of precision 18 one sorted insert
+        // at a time would take billions of moves.
+        let mut hll = HyperLogLog::<u64>::new(18);
+        let mut i = 0;
+        while hll.is_sparse() {
+            hll.insert(&i);
+            i += 1;
+        }
+        assert!(i > 1 << 16);
+        let exact = i as usize;
+        assert!(relative_error(hll.estimate(), exact) < 3.0 * hll.relative_error());
+    }
+
+    #[test]
+    fn test_merge() {
+        let (keys, exact) = stream(40_000, 20_000);
+        let (left, right) = keys.split_at(25_000);
+        let mut whole = HyperLogLog::<u64>::new(12);
+        let mut a = HyperLogLog::<u64>::new(12);
+        let mut b = HyperLogLog::<u64>::new(12);
+        let mut small = HyperLogLog::<u64>::new(12);
+        for key in &keys {
+            whole.insert(key);
+        }
+        for key in left {
+            a.insert(key);
+        }
+        for key in right {
+            b.insert(key);
+        }
+        for key in &keys[..100] {
+            small.insert(key);
+        }
+        a.merge(&b).unwrap();
+        assert_eq!(a.to_dense(), whole.to_dense());
+        assert!(relative_error(a.estimate(), exact) < 3.0 * a.relative_error());
+
+        // Merging sparse into dense and dense into sparse.
+        a.merge(&small).unwrap();
+        assert_eq!(a.to_dense(), whole.to_dense());
+        small.merge(&whole).unwrap();
+        assert!(!small.is_sparse());
+        assert_eq!(small.to_dense(), whole.to_dense());
+
+        let mut x = HyperLogLog::<u64>::new(12);
+        let mut y = HyperLogLog::<u64>::new(12);
+        for i in 0..100 {
+            x.insert(&i);
+            y.insert(&(i + 50));
+        }
+        x.merge(&y).unwrap();
+        assert!(x.is_sparse());
+        assert_eq!(x.estimate(), 150);
+
+        assert_eq!(
+            x.merge(&HyperLogLog::new(10)),
+            Err(IncompatiblePrecision(12, 10))
+        );
+    }
+
+    #[test]
+    fn test_serialization() {
+        let mut hll = HyperLogLog::<u64>::new(10);
+        for i in 0..100 {
+            hll.insert(&i);
+        }
+        assert!(hll.is_sparse());
+        let bytes = hll.to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN + 400);
+        let loaded = HyperLogLog::<u64>::from_bytes(&bytes).unwrap();
+        assert!(loaded.is_sparse());
+        assert_eq!(loaded.estimate(), hll.estimate());
+
+        for i in 100..10_000 {
+            hll.insert(&i);
+        }
+        let bytes = hll.to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN + 1024);
+        let loaded = HyperLogLog::<u64>::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.to_dense(), hll.to_dense());
+
+        assert!(matches!(
+            HyperLogLog::<u64>::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(DecodeError::Truncated)
+        ));
+        let mut corrupt = bytes.clone();
+        corrupt[HEADER_LEN] ^= 1;
+        assert!(matches!(
+            HyperLogLog::<u64>::from_bytes(&corrupt),
+            Err(DecodeError::ChecksumMismatch { .. })
+        ));
+        let mut corrupt = bytes;
+        corrupt[6] = 3;
+        assert!(matches!(
+            HyperLogLog::<u64>::from_bytes(&corrupt),
+            Err(DecodeError::Corrupt(_))
+        ));
+    }
+}
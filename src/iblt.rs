@@ -0,0 +1,328 @@
+use fasthash::{murmur3, FastHash};
+use std::error::Error;
+use std::fmt;
+
+// Hash functions per key. Three need the fewest cells per entry to peel,
+// decoding succeeds w.h.p. with about 1.23 cells per entry.
+pub const DEFAULT_HASHES: usize = 3;
+// Cells per expected entry when sizing from an entry count, leaving some
+// margin over the peeling threshold for small tables.
+const CELLS_PER_ENTRY: f64 = 1.5;
+// Seed of the checksum hash, kept apart from the cell seeds 0..k.
+const CHECK_SEED: u32 = 0x5eed_c0de;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IncompatibleTables {
+    CellCount(usize, usize),
+    HashCount(usize, usize),
+}
+
+impl fmt::Display for IncompatibleTables {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IncompatibleTables::CellCount(a, b) => {
+                write!(f, "tables have different cell counts: {} and {}", a, b)
+            }
+            IncompatibleTables::HashCount(a, b) => {
+                write!(f, "tables have different hash counts: {} and {}", a, b)
+            }
+        }
+    }
+}
+
+impl Error for IncompatibleTables {}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Cell {
+    count: i64,
+    key_sum: u64,
+    value_sum: u64,
+    hash_sum: u64,
+}
+
+impl Cell {
+    fn is_empty(&self) -> bool {
+        *self == Cell::default()
+    }
+
+    // A cell holding exactly one entry, inserted (count 1) or deleted (count
+    // -1). The checksum catches cells whose count is +-1 by accident, e.g.
+    // two inserts and a delete of different keys.
+    fn is_pure(&self) -> bool {
+        (self.count == 1 || self.count == -1) && self.hash_sum == check_hash(self.key_sum)
+    }
+
+    fn add(&mut self, key: u64, value: u64, count: i64) {
+        self.count += count;
+        self.key_sum ^= key;
+        self.value_sum ^= value;
+        self.hash_sum ^= check_hash(key);
+    }
+}
+
+fn check_hash(key: u64) -> u64 {
+    murmur3::Hash128_x64::hash_with_seed(key.to_le_bytes(), CHECK_SEED) as u64
+}
+
+// What `list_entries` recovered from a table.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ListedEntries {
+    // Entries with a net insert, as (key, value).
+    pub inserted: Vec<(u64, u64)>,
+    // Entries with a net delete, as (key, value). After `subtract` these
+    // are the entries only the other table had.
+    pub deleted: Vec<(u64, u64)>,
+    // Whether every entry was recovered. When false the table held more
+    // entries than it can decode and the lists are only part of them.
+    pub complete: bool,
+}
+
+// InvertibleBloomLookupTable (Goodrich and Mitzenmacher, "Invertible Bloom
+// Lookup Tables") stores key/value pairs in cells that only keep a count and
+// the xor of the keys, values and key checksums that hashed to them. A table
+// is as large as the number of entries it must decode, not the number it
+// has seen: deletes cancel inserts exactly, so two replicas can each build a
+// table over their own keys, `subtract` one from the other and decode just
+// the difference with `list_entries`.
+//
+// The cells are split into k equal partitions and a key hashes to one cell
+// in each, so its k cells are always distinct. Keys are u64; hash larger keys
+// down first and keep the mapping on the side.
+#[derive(Clone)]
+pub struct InvertibleBloomLookupTable {
+    cells: Vec<Cell>,
+    hashes: usize,
+}
+
+impl InvertibleBloomLookupTable {
+    // A table that decodes up to `size` entries with high probability.
+    pub fn new(size: usize) -> InvertibleBloomLookupTable {
+        let cells = (size as f64 * CELLS_PER_ENTRY).ceil() as usize;
+        InvertibleBloomLookupTable::with_cells(cells, DEFAULT_HASHES)
+    }
+
+    // The cell count is rounded up to a multiple of `hashes`.
+    pub fn with_cells(cells: usize, hashes: usize) -> InvertibleBloomLookupTable {
+        assert!(hashes > 0, "need at least one hash function");
+        let partition = cells.div_ceil(hashes).max(1);
+        InvertibleBloomLookupTable {
+            cells: vec![Cell::default(); partition * hashes],
+            hashes,
+        }
+    }
+
+    #[inline]
+    fn for_each_cell<F: FnMut(usize)>(&self, key: u64, mut f: F) {
+        let partition = self.cells.len() / self.hashes;
+        let bytes = key.to_le_bytes();
+        for i in 0..self.hashes {
+            let hash = murmur3::Hash128_x64::hash_with_seed(bytes, i as u32) as u64;
+            f(i * partition + (hash % partition as u64) as usize);
+        }
+    }
+
+    fn add(&mut self, key: u64, value: u64, count: i64) {
+        let mut cells = Vec::with_capacity(self.hashes);
+        self.for_each_cell(key, |i| cells.push(i));
+        for i in cells {
+            self.cells[i].add(key, value, count);
+        }
+    }
+
+    pub fn insert(&mut self, key: u64, value: u64) {
+        self.add(key, value, 1);
+    }
+
+    // Deletes work whether or not the entry was inserted: deleting one that
+    // wasn't leaves it listed as deleted.
+    pub fn delete(&mut self, key: u64, value: u64) {
+        self.add(key, value, -1);
+    }
+
+    pub fn check_compatible(
+        &self,
+        other: &InvertibleBloomLookupTable,
+    ) -> Result<(), IncompatibleTables> {
+        if self.cells.len() != other.cells.len() {
+            return Err(IncompatibleTables::CellCount(
+                self.cells.len(),
+                other.cells.len(),
+            ));
+        }
+        if self.hashes != other.hashes {
+            return Err(IncompatibleTables::HashCount(self.hashes, other.hashes));
+        }
+        Ok(())
+    }
+
+    // The table of entries in this one but not `other` (listed as inserted)
+    // and in `other` but not this one (listed as deleted). Entries both have
+    // cancel out, so only the difference has to fit in the cells.
+    pub fn subtract(
+        &self,
+        other: &InvertibleBloomLookupTable,
+    ) -> Result<InvertibleBloomLookupTable, IncompatibleTables> {
+        self.check_compatible(other)?;
+        let mut result = self.clone();
+        for (cell, other) in result.cells.iter_mut().zip(&other.cells) {
+            cell.count -= other.count;
+            cell.key_sum ^= other.key_sum;
+            cell.value_sum ^= other.value_sum;
+            cell.hash_sum ^= other.hash_sum;
+        }
+        Ok(result)
+    }
+
+    // Recovers the entries by peeling: a pure cell gives away its entry,
+    // which is then removed from its other cells, possibly leaving more of
+    // them pure. The table itself is left as it is.
+    pub fn list_entries(&self) -> ListedEntries {
+        let mut table = self.clone();
+        let mut entries = ListedEntries::default();
+        let mut pure: Vec<usize> = (0..table.cells.len())
+            .filter(|&i| table.cells[i].is_pure())
+            .collect();
+        while let Some(i) = pure.pop() {
+            // An earlier peel may have emptied or spoilt this cell.
+            let cell = table.cells[i];
+            if !cell.is_pure() {
+                continue;
+            }
+            let entry = (cell.key_sum, cell.value_sum);
+            if cell.count == 1 {
+                entries.inserted.push(entry);
+            } else {
+                entries.deleted.push(entry);
+            }
+            table.add(cell.key_sum, cell.value_sum, -cell.count);
+            table.for_each_cell(cell.key_sum, |j| {
+                if j != i {
+                    pure.push(j);
+                }
+            });
+        }
+        entries.complete = table.cells.iter().all(Cell::is_empty);
+        entries
+    }
+
+    // Number of cells in the table.
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    pub fn hashes(&self) -> usize {
+        self.hashes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut entries: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+        entries.sort_unstable();
+        entries
+    }
+
+    #[test]
+    fn test_insert_delete() {
+        let mut table = InvertibleBloomLookupTable::new(10);
+        table.insert(1, 100);
+        table.insert(2, 200);
+        table.insert(3, 300);
+        table.delete(2, 200);
+        table.delete(4, 400);
+        let entries = table.list_entries();
+        assert!(entries.complete);
+        assert_eq!(sorted(entries.inserted), vec![(1, 100), (3, 300)]);
+        assert_eq!(entries.deleted, vec![(4, 400)]);
+    }
+
+    #[test]
+    fn test_empty() {
+        let table = InvertibleBloomLookupTable::new(10);
+        assert_eq!(
+            table.list_entries(),
+            ListedEntries {
+                complete: true,
+                ..ListedEntries::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_set_reconciliation() {
+        // Two replicas of 10_000 keys that differ in 50 each way.
+        let mut a = InvertibleBloomLookupTable::new(100);
+        let mut b = InvertibleBloomLookupTable::new(100);
+        for key in 0..10_000u64 {
+            a.insert(key, key * 2);
+        }
+        for key in 50..10_050u64 {
+            b.insert(key, key * 2);
+        }
+        let entries = a.subtract(&b).unwrap().list_entries();
+        assert!(entries.complete);
+        let only_a: Vec<(u64, u64)> = (0..50).map(|key| (key, key * 2)).collect();
+        let only_b: Vec<(u64, u64)> = (10_000..10_050).map(|key| (key, key * 2)).collect();
+        assert_eq!(sorted(entries.inserted), only_a);
+        assert_eq!(sorted(entries.deleted), only_b);
+    }
+
+    #[test]
+    fn test_partial_decode() {
+        // Far more entries than cells: peeling gets stuck.
+        let mut table = InvertibleBloomLookupTable::new(10);
+        for key in 0..1000 {
+            table.insert(key, key);
+        }
+        let entries = table.list_entries();
+        assert!(!entries.complete);
+        assert!(entries.inserted.len() < 1000);
+        // Whatever was listed is real.
+        assert!(entries
+            .inserted
+            .iter()
+            .all(|&(key, value)| key < 1000 && key == value));
+        assert!(entries.deleted.is_empty());
+    }
+
+    #[test]
+    fn test_decode_at_capacity() {
+        let mut failures = 0;
+        for seed in 0..20u64 {
+            let mut table = InvertibleBloomLookupTable::new(1000);
+            for key in 0..1000u64 {
+                table.insert(key.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ seed, key);
+            }
+            let entries = table.list_entries();
+            if entries.complete {
+                assert_eq!(entries.inserted.len(), 1000);
+            } else {
+                failures += 1;
+            }
+        }
+        assert!(failures <= 1);
+    }
+
+    #[test]
+    fn test_incompatible() {
+        let a = InvertibleBloomLookupTable::with_cells(30, 3);
+        let b = InvertibleBloomLookupTable::with_cells(60, 3);
+        let c = InvertibleBloomLookupTable::with_cells(30, 5);
+        assert_eq!(a.len(), 30);
+        assert_eq!(c.len(), 30);
+        assert!(matches!(
+            a.subtract(&b),
+            Err(IncompatibleTables::CellCount(30, 60))
+        ));
+        assert!(matches!(
+            a.subtract(&c),
+            Err(IncompatibleTables::HashCount(3, 5))
+        ));
+    }
+}
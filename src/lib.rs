@@ -1,4 +1,21 @@
 pub mod linkedlist;
 pub mod nodes;
+pub mod binary_fuse_filter;
 pub mod bloom_filter;
+pub mod bloom_filter_builder;
+pub mod bloom_hasher;
+pub mod blocked_bloom_filter;
+pub mod concurrent_bloom_filter;
+pub mod count_min_sketch;
+pub mod counting_bloom_filter;
+pub mod cuckoo_filter;
+pub mod iblt;
+pub mod mmap_bloom_filter;
+pub mod quotient_filter;
+pub mod rotating_bloom_filter;
+pub mod scalable_bloom_filter;
+pub mod serialization;
+pub mod stable_bloom_filter;
 pub mod hashtable;
+pub mod hyperloglog;
+pub mod xor_filter;
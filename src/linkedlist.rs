@@ -10,6 +10,12 @@ pub struct DoublyLinkedList<T> {
     size: usize,
 }
 
+impl<T: Copy + Debug> Default for DoublyLinkedList<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T: Copy + Debug> DoublyLinkedList<T> {
     pub fn new() -> Self {
         DoublyLinkedList {
@@ -27,7 +33,7 @@ impl<T: Copy + Debug> DoublyLinkedList<T> {
                 None => None,
                 Some(tail) => {
                     let popped_node = head.clone();
-                    if Rc::ptr_eq(&head, &tail) {
+                    if Rc::ptr_eq(head, tail) {
                         self.head = None;
                         self.tail = None;
                     } else {
@@ -67,7 +73,7 @@ impl<T: Copy + Debug> DoublyLinkedList<T> {
                 None => None,
                 Some(head) => {
                     let popped_node = tail.clone();
-                    if Rc::ptr_eq(&head, &tail) {
+                    if Rc::ptr_eq(head, tail) {
                         self.head = None;
                         self.tail = None;
                     } else {
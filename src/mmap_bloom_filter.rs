@@ -0,0 +1,522 @@
+use crate::bloom_filter::{Header, KeyBytes, HEADER_LEN};
+use crate::bloom_filter_builder::BloomFilterBuilder;
+use crate::bloom_hasher::{BloomHasher, HashStrategy};
+use crate::serialization::DecodeError;
+use bitvec::prelude::*;
+use memmap2::{Mmap, MmapMut};
+use std::fs::{File, OpenOptions};
+use std::hash::Hash;
+use std::io;
+use std::marker::PhantomData;
+use std::path::Path;
+
+// File backed bloom filters. The file holds a filter in exactly the format
+// BloomFilter::write_to produces, and the bit array is memory mapped straight
+// from it so the filter can be far larger than RAM. A file saved with
+// `write_to` can be opened here and a flushed file can be loaded with
+// `BloomFilter::read_from`.
+//
+// Opening a file only checks its header and length, so it costs the same for
+// any size of filter. Reading the whole payload to check the checksum is left
+// to `verify`. Inserts through MmapBloomFilterMut leave the stored checksum
+// stale until `flush` (or dropping the filter) recomputes it, which reads the
+// whole payload once; `flush_bits` writes the bits out without it. A file
+// crashed with a stale checksum still opens with its bits intact, and
+// `update_checksum` repairs it.
+
+// Checks the header and length of a mapped file.
+fn validate(bytes: &[u8]) -> Result<Header, DecodeError> {
+    let header_bytes: &[u8; HEADER_LEN] = bytes
+        .get(..HEADER_LEN)
+        .ok_or(DecodeError::Truncated)?
+        .try_into()
+        .unwrap();
+    let header = Header::decode(header_bytes)?;
+    let payload = &bytes[HEADER_LEN..];
+    let expected_len = header
+        .words()
+        .checked_mul(8)
+        .ok_or(DecodeError::Corrupt("bit length does not fit in usize"))?;
+    if payload.len() < expected_len {
+        return Err(DecodeError::Truncated);
+    }
+    if payload.len() > expected_len {
+        return Err(DecodeError::Corrupt("trailing bytes after the payload"));
+    }
+    // Fewer than 64 bits, the rest of the last word.
+    if payload.view_bits::<Lsb0>()[header.bits..].any() {
+        return Err(DecodeError::Corrupt("bits set past the end of the filter"));
+    }
+    Ok(header)
+}
+
+// Reads the whole payload, so O(file size).
+fn verify(header: &Header, payload: &[u8]) -> Result<(), DecodeError> {
+    let found = checksum(header, payload);
+    if found != header.checksum {
+        return Err(DecodeError::ChecksumMismatch {
+            expected: header.checksum,
+            found,
+        });
+    }
+    Ok(())
+}
+
+// The payload is the filter's u64 words stored little endian, so bit i is bit
+// i % 8 of byte i / 8 and the bytes can be checksummed as they are.
+fn checksum(header: &Header, payload: &[u8]) -> u32 {
+    let mut crc = header.crc();
+    crc.update(payload);
+    crc.finalize()
+}
+
+// The checksum of an empty filter of `len` payload bytes, built up by doubling
+// the crc of a single zero byte, so a new file's pages are never read.
+fn empty_checksum(header: &Header, len: usize) -> u32 {
+    let mut crc = header.crc();
+    let mut zeros = crc32fast::Hasher::new();
+    zeros.update(&[0]);
+    let mut len = len;
+    while len > 0 {
+        if len & 1 == 1 {
+            crc.combine(&zeros);
+        }
+        let double = zeros.clone();
+        zeros.combine(&double);
+        len >>= 1;
+    }
+    crc.finalize()
+}
+
+fn get_bytes(header: &Header, payload: &[u8], value: &[u8]) -> bool {
+    let bits = payload.view_bits::<Lsb0>();
+    header
+        .strategy
+        .for_each_index(value, header.hashes, header.bits, |pos| bits[pos])
+}
+
+// A filter file opened for queries only.
+pub struct MmapBloomFilter<T: ?Sized> {
+    map: Mmap,
+    header: Header,
+    _key: PhantomData<fn(&T)>,
+}
+
+impl<T: ?Sized> MmapBloomFilter<T> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MmapBloomFilter<T>, DecodeError> {
+        let file = File::open(path)?;
+        // Safety: the map is only valid while no one else truncates or
+        // rewrites the file, the same contract every memory mapped file has.
+        let map = unsafe { Mmap::map(&file)? };
+        let header = validate(&map)?;
+        Ok(MmapBloomFilter {
+            map,
+            header,
+            _key: PhantomData,
+        })
+    }
+
+    pub fn get_bytes(&self, value: &[u8]) -> bool {
+        get_bytes(&self.header, &self.map[HEADER_LEN..], value)
+    }
+
+    // Checks the stored checksum against the bits, reading the whole file.
+    pub fn verify(&self) -> Result<(), DecodeError> {
+        verify(&self.header, &self.map[HEADER_LEN..])
+    }
+
+    // Number of bits in the filter.
+    pub fn len(&self) -> usize {
+        self.header.bits
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.header.bits == 0
+    }
+
+    pub fn hashes(&self) -> usize {
+        self.header.hashes
+    }
+
+    pub fn strategy(&self) -> HashStrategy {
+        self.header.strategy
+    }
+}
+
+impl<T: Hash + ?Sized> MmapBloomFilter<T> {
+    pub fn get(&self, value: &T) -> bool {
+        self.get_bytes(&KeyBytes::of(value))
+    }
+}
+
+// A filter file opened for inserts and queries. Dropping it flushes it like
+// `flush` does.
+pub struct MmapBloomFilterMut<T: ?Sized> {
+    map: MmapMut,
+    header: Header,
+    // Whether bits were set since the stored checksum was computed.
+    stale: bool,
+    _key: PhantomData<fn(&T)>,
+}
+
+impl<T: ?Sized> MmapBloomFilterMut<T> {
+    // Creates (or truncates) the file at `path` and sizes an empty filter in
+    // it the way BloomFilter::new would, DoubleHashing included for files
+    // past 2^32 bits. Invalid parameters fail with an InvalidInput error
+    // wrapping the BloomFilterError, before the file is touched.
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        size: usize,
+        fp_rate: f64,
+    ) -> io::Result<MmapBloomFilterMut<T>> {
+        let builder = BloomFilterBuilder::new()
+            .expected_items(size)
+            .fp_rate(fp_rate);
+        MmapBloomFilterMut::create_from(path, &builder)
+    }
+
+    // Murmur3 is rejected for files past 2^32 bits.
+    pub fn create_with_strategy<P: AsRef<Path>>(
+        path: P,
+        size: usize,
+        fp_rate: f64,
+        strategy: HashStrategy,
+    ) -> io::Result<MmapBloomFilterMut<T>> {
+        let builder = BloomFilterBuilder::new()
+            .expected_items(size)
+            .fp_rate(fp_rate)
+            .strategy(strategy);
+        MmapBloomFilterMut::create_from(path, &builder)
+    }
+
+    fn create_from<P: AsRef<Path>>(
+        path: P,
+        builder: &BloomFilterBuilder,
+    ) -> io::Result<MmapBloomFilterMut<T>> {
+        let params = builder
+            .params()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let mut header = Header {
+            strategy: params.strategy,
+            hashes: params.hashes,
+            bits: params.bits,
+            checksum: 0,
+        };
+        header.checksum = empty_checksum(&header, header.words() * 8);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len((HEADER_LEN + header.words() * 8) as u64)?;
+        // Safety: see MmapBloomFilter::open.
+        let map = unsafe { MmapMut::map_mut(&file)? };
+        let mut filter = MmapBloomFilterMut {
+            map,
+            header,
+            stale: false,
+            _key: PhantomData,
+        };
+        filter.map[..HEADER_LEN].copy_from_slice(&filter.header.encode());
+        filter.flush_bits()?;
+        Ok(filter)
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MmapBloomFilterMut<T>, DecodeError> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        // Safety: see MmapBloomFilter::open.
+        let map = unsafe { MmapMut::map_mut(&file)? };
+        let header = validate(&map)?;
+        Ok(MmapBloomFilterMut {
+            map,
+            header,
+            stale: false,
+            _key: PhantomData,
+        })
+    }
+
+    pub fn insert_bytes(&mut self, value: &[u8]) {
+        self.stale = true;
+        let header = &self.header;
+        let bits = self.map[HEADER_LEN..].view_bits_mut::<Lsb0>();
+        header
+            .strategy
+            .for_each_index(value, header.hashes, header.bits, |pos| {
+                bits.set(pos, true);
+                true
+            });
+    }
+
+    pub fn get_bytes(&self, value: &[u8]) -> bool {
+        get_bytes(&self.header, &self.map[HEADER_LEN..], value)
+    }
+
+    // Writes the filter out to disk so it passes `verify` and loads with
+    // BloomFilter::read_from. If bits were set since the checksum was last
+    // computed it is recomputed first, which reads the whole payload.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.stale {
+            self.write_checksum();
+        }
+        self.flush_bits()
+    }
+
+    // Writes the bits set so far out to disk, leaving the stored checksum as
+    // it is. Only the dirty pages are written.
+    pub fn flush_bits(&self) -> io::Result<()> {
+        self.map.flush()
+    }
+
+    // Recomputes the checksum whether or not bits were set through this
+    // filter, for a file left with a stale one, then flushes.
+    pub fn update_checksum(&mut self) -> io::Result<()> {
+        self.write_checksum();
+        self.flush_bits()
+    }
+
+    fn write_checksum(&mut self) {
+        self.header.checksum = checksum(&self.header, &self.map[HEADER_LEN..]);
+        self.map[..HEADER_LEN].copy_from_slice(&self.header.encode());
+        self.stale = false;
+    }
+
+    // Checks the stored checksum against the bits, reading the whole file.
+    pub fn verify(&self) -> Result<(), DecodeError> {
+        verify(&self.header, &self.map[HEADER_LEN..])
+    }
+
+    // Number of bits in the filter.
+    pub fn len(&self) -> usize {
+        self.header.bits
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.header.bits == 0
+    }
+
+    pub fn hashes(&self) -> usize {
+        self.header.hashes
+    }
+
+    pub fn strategy(&self) -> HashStrategy {
+        self.header.strategy
+    }
+}
+
+impl<T: ?Sized> Drop for MmapBloomFilterMut<T> {
+    fn drop(&mut self) {
+        // Nowhere to report an error from here; call flush first to see it.
+        let _ = self.flush();
+    }
+}
+
+impl<T: Hash + ?Sized> MmapBloomFilterMut<T> {
+    pub fn insert(&mut self, value: &T) {
+        self.insert_bytes(&KeyBytes::of(value));
+    }
+
+    pub fn get(&self, value: &T) -> bool {
+        self.get_bytes(&KeyBytes::of(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bloom_filter::BloomFilter;
+    use crate::bloom_filter_builder::BloomFilterError;
+    use std::fs;
+
+    #[test]
+    fn test_create_insert_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("filter.bloom");
+
+        let mut filter = MmapBloomFilterMut::<u64>::create(&path, 1000, 0.01).unwrap();
+        for i in 0..1000 {
+            filter.insert(&i);
+        }
+        assert!(filter.get(&10));
+        filter.flush().unwrap();
+        drop(filter);
+
+        let filter = MmapBloomFilter::<u64>::open(&path).unwrap();
+        for i in 0..1000 {
+            assert!(filter.get(&i));
+        }
+        assert!((1000..2000).filter(|i| filter.get(i)).count() < 50);
+    }
+
+    #[test]
+    fn test_same_format_as_bloom_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("filter.bloom");
+
+        let mut plain = BloomFilter::<u64>::with_strategy(1000, 0.01, HashStrategy::DoubleHashing);
+        for i in 0..500 {
+            plain.insert(&i);
+        }
+        plain.write_to(fs::File::create(&path).unwrap()).unwrap();
+
+        let read_only = MmapBloomFilter::<u64>::open(&path).unwrap();
+        assert_eq!(read_only.len(), plain.len());
+        assert_eq!(read_only.hashes(), plain.hashes());
+        assert_eq!(read_only.strategy(), HashStrategy::DoubleHashing);
+        for i in 0..500 {
+            assert!(read_only.get(&i));
+        }
+        drop(read_only);
+
+        let mut read_write = MmapBloomFilterMut::<u64>::open(&path).unwrap();
+        for i in 500..1000 {
+            read_write.insert(&i);
+            plain.insert(&i);
+        }
+        read_write.flush().unwrap();
+        drop(read_write);
+
+        assert_eq!(fs::read(&path).unwrap(), plain.to_bytes());
+        let loaded = BloomFilter::<u64>::read_from(fs::File::open(&path).unwrap()).unwrap();
+        for i in 0..1000 {
+            assert!(loaded.get(&i));
+        }
+    }
+
+    #[test]
+    fn test_new_file_matches_empty_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("filter.bloom");
+        for size in [1, 100, 1000, 123_457] {
+            drop(MmapBloomFilterMut::<u64>::create(&path, size, 0.01).unwrap());
+            assert_eq!(
+                fs::read(&path).unwrap(),
+                BloomFilter::<u64>::new(size, 0.01).to_bytes()
+            );
+            MmapBloomFilter::<u64>::open(&path)
+                .unwrap()
+                .verify()
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_stale_checksum_still_opens() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("filter.bloom");
+        let mut filter = MmapBloomFilterMut::<str>::create(&path, 100, 0.01).unwrap();
+        filter.insert("test");
+        filter.flush_bits().unwrap();
+        // Lost without a flush, as after a crash.
+        std::mem::forget(filter);
+
+        let filter = MmapBloomFilter::<str>::open(&path).unwrap();
+        assert!(filter.get("test"));
+        assert!(matches!(
+            filter.verify(),
+            Err(DecodeError::ChecksumMismatch { .. })
+        ));
+        drop(filter);
+
+        let mut filter = MmapBloomFilterMut::<str>::open(&path).unwrap();
+        assert!(filter.verify().is_err());
+        filter.update_checksum().unwrap();
+        filter.verify().unwrap();
+        drop(filter);
+        let loaded = BloomFilter::<str>::read_from(fs::File::open(&path).unwrap()).unwrap();
+        assert!(loaded.get("test"));
+    }
+
+    #[test]
+    fn test_flushed_file_loads() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("filter.bloom");
+        let mut filter = MmapBloomFilterMut::<u64>::create(&path, 1000, 0.01).unwrap();
+        for i in 0..500 {
+            filter.insert(&i);
+        }
+        filter.flush().unwrap();
+        let loaded = BloomFilter::<u64>::read_from(fs::File::open(&path).unwrap()).unwrap();
+        assert!((0..500).all(|i| loaded.get(&i)));
+
+        // Dropping flushes the same way.
+        for i in 500..1000 {
+            filter.insert(&i);
+        }
+        drop(filter);
+        let loaded = BloomFilter::<u64>::read_from(fs::File::open(&path).unwrap()).unwrap();
+        assert!((0..1000).all(|i| loaded.get(&i)));
+    }
+
+    #[test]
+    fn test_create_rejects_bad_parameters() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("filter.bloom");
+        for (size, fp_rate, expected) in [
+            (0, 0.01, BloomFilterError::ZeroItems),
+            (100, 1.0, BloomFilterError::InvalidFpRate(1.0)),
+        ] {
+            let err = MmapBloomFilterMut::<u64>::create(&path, size, fp_rate)
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            let inner = err.get_ref().unwrap().downcast_ref::<BloomFilterError>();
+            assert_eq!(inner, Some(&expected));
+            assert!(!path.exists());
+        }
+
+        // A billion items at 0.1% need more bits than murmur3 reaches.
+        let err = MmapBloomFilterMut::<u64>::create_with_strategy(
+            &path,
+            1_000_000_000,
+            0.001,
+            HashStrategy::Murmur3,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_open_rejects_bad_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("filter.bloom");
+        let mut bytes = BloomFilter::<str>::new(100, 0.01).to_bytes();
+
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(matches!(
+            MmapBloomFilter::<str>::open(&path),
+            Err(DecodeError::Truncated)
+        ));
+
+        fs::write(&path, &bytes[..10]).unwrap();
+        assert!(matches!(
+            MmapBloomFilterMut::<str>::open(&path),
+            Err(DecodeError::Truncated)
+        ));
+
+        // Opening doesn't read the payload; verify does.
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+        let filter = MmapBloomFilter::<str>::open(&path).unwrap();
+        assert!(matches!(
+            filter.verify(),
+            Err(DecodeError::ChecksumMismatch { .. })
+        ));
+        drop(filter);
+
+        bytes[0] = 0;
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            MmapBloomFilter::<str>::open(&path),
+            Err(DecodeError::BadMagic)
+        ));
+
+        assert!(matches!(
+            MmapBloomFilter::<str>::open(dir.path().join("missing")),
+            Err(DecodeError::Io(_))
+        ));
+    }
+}
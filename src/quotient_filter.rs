@@ -0,0 +1,658 @@
+use crate::bloom_filter::KeyBytes;
+use crate::bloom_hasher::double_hash;
+use bitvec::prelude::*;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+// The three metadata bits at the start of every slot, followed by the
+// remainder.
+const OCCUPIED: usize = 0;
+const CONTINUATION: usize = 1;
+const SHIFTED: usize = 2;
+const METADATA_BITS: usize = 3;
+// Load the table is sized for. Runs and clusters get long quickly past it,
+// which makes every operation slower, though inserts only fail when every
+// slot is taken.
+const MAX_LOAD: f64 = 0.75;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuotientFilterError {
+    // Every slot is taken. The filter is unchanged; `grow` makes room.
+    Full,
+    // Growing moves a bit from the remainder to the quotient and the
+    // remainder has a single bit left.
+    RemainderExhausted,
+    // Only filters whose fingerprints have the same number of bits can be
+    // merged, as (self, other).
+    FingerprintBits(usize, usize),
+}
+
+impl fmt::Display for QuotientFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuotientFilterError::Full => write!(f, "quotient filter is full"),
+            QuotientFilterError::RemainderExhausted => {
+                write!(f, "no remainder bits left to grow the quotient filter")
+            }
+            QuotientFilterError::FingerprintBits(a, b) => {
+                write!(f, "fingerprint lengths differ: {} and {} bits", a, b)
+            }
+        }
+    }
+}
+
+impl Error for QuotientFilterError {}
+
+// Remainder bits for a target false positive rate. A lookup only compares
+// against the run of its own quotient, so at load a the rate is about
+// a * 2^-r, which 2^-r bounds.
+pub fn remainder_bits(fp_rate: f64) -> usize {
+    ((1.0 / fp_rate).log2().ceil() as usize).clamp(1, 32)
+}
+
+// QuotientFilter stores a p bit fingerprint of every key, split into a
+// quotient (the high q bits) that picks one of 2^q slots and a remainder (the
+// low r bits) that is stored in it. Remainders of the same quotient are kept
+// sorted in a run of consecutive slots, shifted right past the runs of
+// earlier quotients when needed, and runs without an empty slot between them
+// form a cluster. Three bits per slot recover the quotients:
+//   - occupied: some key has this slot's index as its quotient,
+//   - continuation: the slot belongs to the same run as the one before it,
+//   - shifted: the remainder is not in its quotient's slot.
+// Since the full fingerprint can be rebuilt from a slot, the filter can be
+// resized and merged without the original keys (Bender et al., "Don't Thrash:
+// How to Cache Your Hash on Flash"). It is a multiset: inserting a key twice
+// stores it twice and each delete removes one copy.
+pub struct QuotientFilter<T: ?Sized> {
+    slots: BitVec<u64, Lsb0>,
+    quotient_bits: usize,
+    remainder_bits: usize,
+    len: usize,
+    _key: PhantomData<fn(&T)>,
+}
+
+impl<T: ?Sized> QuotientFilter<T> {
+    // Panics when size is 0 or fp_rate isn't strictly between 0 and 1.
+    pub fn new(size: usize, fp_rate: f64) -> QuotientFilter<T> {
+        assert!(size > 0, "expected items must be positive");
+        assert!(
+            fp_rate > 0.0 && fp_rate < 1.0,
+            "false positive rate must be between 0 and 1 exclusive, got {}",
+            fp_rate
+        );
+        let quotient_bits = ((size as f64 / MAX_LOAD).log2().ceil() as usize).max(1);
+        QuotientFilter::with_bits(quotient_bits, remainder_bits(fp_rate))
+    }
+
+    // A filter with 2^quotient_bits slots storing remainder_bits per key.
+    pub fn with_bits(quotient_bits: usize, remainder_bits: usize) -> QuotientFilter<T> {
+        assert!(
+            quotient_bits >= 1 && remainder_bits >= 1 && quotient_bits + remainder_bits <= 64,
+            "quotient and remainder need at least one bit each and at most 64 together"
+        );
+        QuotientFilter {
+            slots: bitvec![u64, Lsb0; 0; (1 << quotient_bits) * (METADATA_BITS + remainder_bits)],
+            quotient_bits,
+            remainder_bits,
+            len: 0,
+            _key: PhantomData,
+        }
+    }
+
+    #[inline]
+    fn width(&self) -> usize {
+        METADATA_BITS + self.remainder_bits
+    }
+
+    #[inline]
+    fn mask(&self) -> usize {
+        (1 << self.quotient_bits) - 1
+    }
+
+    #[inline]
+    fn next(&self, slot: usize) -> usize {
+        (slot + 1) & self.mask()
+    }
+
+    #[inline]
+    fn prev(&self, slot: usize) -> usize {
+        slot.wrapping_sub(1) & self.mask()
+    }
+
+    #[inline]
+    fn bit(&self, slot: usize, which: usize) -> bool {
+        self.slots[slot * self.width() + which]
+    }
+
+    #[inline]
+    fn set_bit(&mut self, slot: usize, which: usize, value: bool) {
+        let width = self.width();
+        self.slots.set(slot * width + which, value);
+    }
+
+    #[inline]
+    fn remainder(&self, slot: usize) -> u64 {
+        let start = slot * self.width() + METADATA_BITS;
+        self.slots[start..start + self.remainder_bits].load_le()
+    }
+
+    #[inline]
+    fn set_remainder(&mut self, slot: usize, remainder: u64) {
+        let start = slot * self.width() + METADATA_BITS;
+        self.slots[start..start + self.remainder_bits].store_le(remainder);
+    }
+
+    #[inline]
+    fn is_empty_slot(&self, slot: usize) -> bool {
+        let start = slot * self.width();
+        self.slots[start..start + METADATA_BITS].not_any()
+    }
+
+    #[inline]
+    fn fingerprint(&self, value: &[u8]) -> u64 {
+        let (hash, _) = double_hash(value);
+        hash >> (64 - self.fingerprint_bits())
+    }
+
+    #[inline]
+    fn split(&self, fingerprint: u64) -> (usize, u64) {
+        let quotient = (fingerprint >> self.remainder_bits) as usize;
+        (quotient, fingerprint & ((1 << self.remainder_bits) - 1))
+    }
+
+    // The first slot of the cluster `slot` is in, or `slot` itself when it
+    // is empty. Nothing before it is shifted into the cluster.
+    fn cluster_start(&self, slot: usize) -> usize {
+        let mut slot = slot;
+        while self.bit(slot, SHIFTED) {
+            slot = self.prev(slot);
+        }
+        slot
+    }
+
+    // Where the run of `quotient` starts. Its occupied bit must be set.
+    fn run_start(&self, quotient: usize) -> usize {
+        // Walk the cluster from its start, skipping one run for every
+        // occupied slot until reaching the quotient.
+        let mut canonical = self.cluster_start(quotient);
+        let mut run = canonical;
+        while canonical != quotient {
+            loop {
+                run = self.next(run);
+                if !self.bit(run, CONTINUATION) {
+                    break;
+                }
+            }
+            loop {
+                canonical = self.next(canonical);
+                if self.bit(canonical, OCCUPIED) {
+                    break;
+                }
+            }
+        }
+        run
+    }
+
+    // The entries from the cluster starting at `start` up to the next empty
+    // slot, as sorted (quotient, remainder) pairs with each quotient given as
+    // its offset from `start`, and the number of slots they take up. The
+    // j-th run found belongs to the j-th occupied slot.
+    fn decode(&self, start: usize) -> (Vec<(usize, u64)>, usize) {
+        let mut entries = Vec::new();
+        let mut quotients = VecDeque::new();
+        let mut quotient = 0;
+        let mut offset = 0;
+        while offset <= self.mask() {
+            let slot = (start + offset) & self.mask();
+            if self.is_empty_slot(slot) {
+                break;
+            }
+            if self.bit(slot, OCCUPIED) {
+                quotients.push_back(offset);
+            }
+            if !self.bit(slot, CONTINUATION) {
+                quotient = quotients
+                    .pop_front()
+                    .expect("every run has an occupied slot before it");
+            }
+            entries.push((quotient, self.remainder(slot)));
+            offset += 1;
+        }
+        (entries, offset)
+    }
+
+    // Writes sorted entries, as returned by `decode`, back into `len` slots
+    // from `start`. Every entry goes in its quotient's slot or the first
+    // free one after it, which keeps runs in quotient order.
+    fn layout(&mut self, start: usize, entries: &[(usize, u64)], len: usize) {
+        let width = self.width();
+        for offset in 0..len {
+            let slot = (start + offset) & self.mask();
+            self.slots[slot * width..(slot + 1) * width].fill(false);
+        }
+        let mut next = 0;
+        let mut previous = None;
+        for &(quotient, remainder) in entries {
+            let offset = next.max(quotient);
+            let slot = (start + offset) & self.mask();
+            self.set_bit((start + quotient) & self.mask(), OCCUPIED, true);
+            self.set_bit(slot, CONTINUATION, previous == Some(quotient));
+            self.set_bit(slot, SHIFTED, offset != quotient);
+            self.set_remainder(slot, remainder);
+            next = offset + 1;
+            previous = Some(quotient);
+        }
+        debug_assert!(next <= len);
+    }
+
+    fn insert_fingerprint(&mut self, fingerprint: u64) -> Result<(), QuotientFilterError> {
+        if self.len == self.capacity() {
+            return Err(QuotientFilterError::Full);
+        }
+        let (quotient, remainder) = self.split(fingerprint);
+        let start = self.cluster_start(quotient);
+        let (mut entries, len) = self.decode(start);
+        let entry = (quotient.wrapping_sub(start) & self.mask(), remainder);
+        let at = entries.partition_point(|&other| other <= entry);
+        entries.insert(at, entry);
+        // The entries fill the region without gaps, so the new one takes
+        // exactly the empty slot that ended it.
+        self.layout(start, &entries, len + 1);
+        self.len += 1;
+        Ok(())
+    }
+
+    fn delete_fingerprint(&mut self, fingerprint: u64) -> bool {
+        let (quotient, remainder) = self.split(fingerprint);
+        if !self.bit(quotient, OCCUPIED) {
+            return false;
+        }
+        let start = self.cluster_start(quotient);
+        let (mut entries, len) = self.decode(start);
+        let entry = (quotient.wrapping_sub(start) & self.mask(), remainder);
+        match entries.binary_search(&entry) {
+            Ok(at) => {
+                entries.remove(at);
+                self.layout(start, &entries, len);
+                self.len -= 1;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    // Every stored fingerprint, duplicates included.
+    fn fingerprints(&self) -> Vec<u64> {
+        let mut fingerprints = Vec::with_capacity(self.len);
+        if self.len == 0 {
+            return fingerprints;
+        }
+        // Start on an empty slot so every region decoded begins a cluster.
+        // A full table is a single region from any cluster start.
+        let base = (0..self.capacity())
+            .find(|&slot| self.is_empty_slot(slot))
+            .unwrap_or_else(|| self.cluster_start(0));
+        let mut offset = 0;
+        while offset < self.capacity() {
+            let slot = (base + offset) & self.mask();
+            if self.is_empty_slot(slot) {
+                offset += 1;
+                continue;
+            }
+            let (entries, len) = self.decode(slot);
+            for (quotient, remainder) in entries {
+                let quotient = (slot + quotient) & self.mask();
+                fingerprints.push((quotient as u64) << self.remainder_bits | remainder);
+            }
+            offset += len;
+        }
+        fingerprints
+    }
+
+    pub fn insert_bytes(&mut self, value: &[u8]) -> Result<(), QuotientFilterError> {
+        self.insert_fingerprint(self.fingerprint(value))
+    }
+
+    pub fn contains_bytes(&self, value: &[u8]) -> bool {
+        let (quotient, remainder) = self.split(self.fingerprint(value));
+        if !self.bit(quotient, OCCUPIED) {
+            return false;
+        }
+        let mut slot = self.run_start(quotient);
+        loop {
+            let found = self.remainder(slot);
+            if found >= remainder {
+                return found == remainder;
+            }
+            slot = self.next(slot);
+            if !self.bit(slot, CONTINUATION) {
+                return false;
+            }
+        }
+    }
+
+    // Removes one copy of the key's fingerprint. Only delete keys that were
+    // inserted: deleting a false positive removes another key's fingerprint.
+    pub fn delete_bytes(&mut self, value: &[u8]) -> bool {
+        self.delete_fingerprint(self.fingerprint(value))
+    }
+
+    // Doubles the number of slots. The fingerprints stay the same, so one
+    // remainder bit becomes a quotient bit, and the false positive rate at a
+    // given load doubles.
+    pub fn grow(&mut self) -> Result<(), QuotientFilterError> {
+        if self.remainder_bits == 1 {
+            return Err(QuotientFilterError::RemainderExhausted);
+        }
+        let mut grown = QuotientFilter::with_bits(self.quotient_bits + 1, self.remainder_bits - 1);
+        for fingerprint in self.fingerprints() {
+            grown
+                .insert_fingerprint(fingerprint)
+                .expect("a grown filter has room for every fingerprint");
+        }
+        *self = grown;
+        Ok(())
+    }
+
+    // A filter holding the fingerprints of both, with at least as many slots
+    // as the larger one and more if both together don't fit in it.
+    pub fn merge(
+        &self,
+        other: &QuotientFilter<T>,
+    ) -> Result<QuotientFilter<T>, QuotientFilterError> {
+        let bits = self.fingerprint_bits();
+        if bits != other.fingerprint_bits() {
+            return Err(QuotientFilterError::FingerprintBits(
+                bits,
+                other.fingerprint_bits(),
+            ));
+        }
+        let len = self.len + other.len;
+        let mut quotient_bits = self.quotient_bits.max(other.quotient_bits);
+        while ((1usize << quotient_bits) as f64 * MAX_LOAD) < len as f64 {
+            quotient_bits += 1;
+        }
+        if quotient_bits >= bits {
+            return Err(QuotientFilterError::RemainderExhausted);
+        }
+        let mut merged = QuotientFilter::with_bits(quotient_bits, bits - quotient_bits);
+        for fingerprint in self.fingerprints().into_iter().chain(other.fingerprints()) {
+            merged
+                .insert_fingerprint(fingerprint)
+                .expect("a merged filter has room for both");
+        }
+        Ok(merged)
+    }
+
+    // Number of fingerprints stored.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Number of slots.
+    pub fn capacity(&self) -> usize {
+        1 << self.quotient_bits
+    }
+
+    pub fn load_factor(&self) -> f64 {
+        self.len as f64 / self.capacity() as f64
+    }
+
+    pub fn quotient_bits(&self) -> usize {
+        self.quotient_bits
+    }
+
+    pub fn remainder_bits(&self) -> usize {
+        self.remainder_bits
+    }
+
+    pub fn fingerprint_bits(&self) -> usize {
+        self.quotient_bits + self.remainder_bits
+    }
+
+    // Expected false positive rate at the current load.
+    pub fn fp_rate(&self) -> f64 {
+        1.0 - (-self.load_factor() / 2.0f64.powi(self.remainder_bits as i32)).exp()
+    }
+}
+
+impl<T: Hash + ?Sized> QuotientFilter<T> {
+    pub fn insert(&mut self, value: &T) -> Result<(), QuotientFilterError> {
+        self.insert_bytes(&KeyBytes::of(value))
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.contains_bytes(&KeyBytes::of(value))
+    }
+
+    pub fn delete(&mut self, value: &T) -> bool {
+        self.delete_bytes(&KeyBytes::of(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Checks the metadata bits against what the layout promises, and that
+    // the filter holds exactly the `expected` fingerprints.
+    fn check_invariants<T: ?Sized>(filter: &QuotientFilter<T>, expected: &[u64]) {
+        let mut used = 0;
+        let mut runs = 0;
+        for slot in 0..filter.capacity() {
+            if filter.is_empty_slot(slot) {
+                continue;
+            }
+            used += 1;
+            let prev = filter.prev(slot);
+            if filter.bit(slot, CONTINUATION) {
+                // A run never starts in the middle of another, and is sorted.
+                assert!(filter.bit(slot, SHIFTED), "slot {}", slot);
+                assert!(!filter.is_empty_slot(prev), "slot {}", slot);
+                assert!(filter.remainder(prev) <= filter.remainder(slot));
+            } else {
+                runs += 1;
+            }
+            if filter.bit(slot, SHIFTED) {
+                assert!(!filter.is_empty_slot(prev), "slot {}", slot);
+            }
+        }
+        assert_eq!(used, filter.len());
+
+        let mut occupied = 0;
+        for quotient in 0..filter.capacity() {
+            if !filter.bit(quotient, OCCUPIED) {
+                continue;
+            }
+            occupied += 1;
+            // Each occupied slot has a run, shifted exactly when it doesn't
+            // start in its own slot.
+            let start = filter.run_start(quotient);
+            assert!(!filter.bit(start, CONTINUATION), "quotient {}", quotient);
+            assert_eq!(filter.bit(start, SHIFTED), start != quotient);
+        }
+        assert_eq!(occupied, runs);
+
+        let mut found = filter.fingerprints();
+        let mut expected = expected.to_vec();
+        found.sort_unstable();
+        expected.sort_unstable();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut filter = QuotientFilter::new(2, 0.001);
+        filter.insert("test").unwrap();
+        assert!(!filter.contains("bar"));
+        assert!(!filter.contains("foo"));
+        assert!(!filter.contains("bazz"));
+        assert!(filter.contains("test"));
+    }
+
+    #[test]
+    fn test_delete() {
+        let mut filter = QuotientFilter::<u64>::new(1000, 0.001);
+        for i in 0..1000 {
+            filter.insert(&i).unwrap();
+        }
+        assert_eq!(filter.len(), 1000);
+        for i in (0..1000).step_by(2) {
+            assert!(filter.delete(&i));
+        }
+        assert_eq!(filter.len(), 500);
+        for i in (1..1000).step_by(2) {
+            assert!(filter.contains(&i));
+        }
+        let still_found = (0..1000).step_by(2).filter(|i| filter.contains(i)).count();
+        assert!(still_found < 5);
+        assert!(!filter.delete(&5000));
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let mut filter = QuotientFilter::<str>::new(10, 0.01);
+        filter.insert("test").unwrap();
+        filter.insert("test").unwrap();
+        assert!(filter.delete("test"));
+        assert!(filter.contains("test"));
+        assert!(filter.delete("test"));
+        assert!(!filter.contains("test"));
+        assert!(filter.is_empty());
+    }
+
+    #[test]
+    fn test_invariants() {
+        // A small table with few quotients gives long runs and clusters that
+        // wrap around the end.
+        let mut filter = QuotientFilter::<u64>::with_bits(5, 6);
+        let mut expected = Vec::new();
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        for step in 0..2000 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let fingerprint = state >> (64 - filter.fingerprint_bits());
+            if !state.is_multiple_of(3) && filter.len() < filter.capacity() {
+                filter.insert_fingerprint(fingerprint).unwrap();
+                expected.push(fingerprint);
+            } else if !expected.is_empty() {
+                let victim = expected.swap_remove(state as usize % expected.len());
+                assert!(filter.delete_fingerprint(victim), "step {}", step);
+            }
+            check_invariants(&filter, &expected);
+        }
+    }
+
+    #[test]
+    fn test_wraparound() {
+        let mut filter = QuotientFilter::<u64>::with_bits(3, 4);
+        // Five keys for the last slot and one for the first: the run of
+        // slot 7 wraps and pushes slot 0's run along.
+        let fingerprints = [0x7f, 0x71, 0x7a, 0x77, 0x71, 0x03];
+        for &fingerprint in &fingerprints {
+            filter.insert_fingerprint(fingerprint).unwrap();
+        }
+        check_invariants(&filter, &fingerprints);
+        assert!(filter.bit(7, OCCUPIED) && filter.bit(0, OCCUPIED));
+        assert_eq!(filter.run_start(0), 4);
+        assert!(filter.delete_fingerprint(0x77));
+        assert!(!filter.delete_fingerprint(0x77));
+        assert_eq!(filter.run_start(0), 3);
+        check_invariants(&filter, &[0x7f, 0x71, 0x7a, 0x71, 0x03]);
+    }
+
+    #[test]
+    fn test_full() {
+        let mut filter = QuotientFilter::<u64>::with_bits(4, 8);
+        let mut inserted = 0;
+        let err = loop {
+            match filter.insert(&inserted) {
+                Ok(()) => inserted += 1,
+                Err(err) => break err,
+            }
+        };
+        assert_eq!(err, QuotientFilterError::Full);
+        assert_eq!(inserted, 16);
+        assert!((0..inserted).all(|i| filter.contains(&i)));
+        filter.grow().unwrap();
+        assert!(filter.insert(&inserted).is_ok());
+        assert!((0..=inserted).all(|i| filter.contains(&i)));
+    }
+
+    #[test]
+    fn test_grow() {
+        let mut filter = QuotientFilter::<u64>::new(1000, 0.001);
+        for i in 0..1000 {
+            filter.insert(&i).unwrap();
+        }
+        let (q, r) = (filter.quotient_bits(), filter.remainder_bits());
+        let fingerprints = filter.fingerprints();
+        filter.grow().unwrap();
+        assert_eq!(filter.quotient_bits(), q + 1);
+        assert_eq!(filter.remainder_bits(), r - 1);
+        assert_eq!(filter.len(), 1000);
+        check_invariants(&filter, &fingerprints);
+        assert!((0..1000).all(|i| filter.contains(&i)));
+
+        let mut tiny = QuotientFilter::<u64>::with_bits(2, 1);
+        assert_eq!(tiny.grow(), Err(QuotientFilterError::RemainderExhausted));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut a = QuotientFilter::<u64>::with_bits(10, 10);
+        let mut b = QuotientFilter::<u64>::with_bits(9, 11);
+        for i in 0..700 {
+            a.insert(&i).unwrap();
+        }
+        for i in 500..1000 {
+            b.insert(&i).unwrap();
+        }
+        let merged = a.merge(&b).unwrap();
+        assert_eq!(merged.len(), 1200);
+        assert_eq!(merged.fingerprint_bits(), 20);
+        assert!(merged.load_factor() <= MAX_LOAD);
+        let mut expected = a.fingerprints();
+        expected.extend(b.fingerprints());
+        check_invariants(&merged, &expected);
+        assert!((0..1000).all(|i| merged.contains(&i)));
+        assert!((1000..2000).filter(|i| merged.contains(i)).count() < 20);
+
+        let other = QuotientFilter::<u64>::with_bits(10, 8);
+        assert!(matches!(
+            a.merge(&other),
+            Err(QuotientFilterError::FingerprintBits(20, 18))
+        ));
+    }
+
+    #[test]
+    fn test_fp_rate() {
+        let mut filter = QuotientFilter::<u64>::new(10_000, 0.01);
+        for i in 0..10_000 {
+            filter.insert(&i).unwrap();
+        }
+        assert!(filter.fp_rate() < 0.01);
+        let false_positives = (10_000..210_000).filter(|i| filter.contains(i)).count();
+        assert!((false_positives as f64 / 200_000.0) < 0.01);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected items must be positive")]
+    fn test_zero_size_panics() {
+        QuotientFilter::<u64>::new(0, 0.01);
+    }
+
+    #[test]
+    #[should_panic(expected = "false positive rate must be between 0 and 1")]
+    fn test_bad_fp_rate_panics() {
+        QuotientFilter::<u64>::new(100, f64::NAN);
+    }
+}
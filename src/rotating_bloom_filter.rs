@@ -0,0 +1,138 @@
+use crate::bloom_filter::{BloomFilter, KeyBytes};
+use std::collections::VecDeque;
+use std::hash::Hash;
+
+// RotatingBloomFilter forgets keys once they fall out of a window of the most
+// recent inserts. The window is split into generations, each a BloomFilter
+// sized for window / generations keys. Inserts go into the newest generation;
+// when it is full the oldest one is dropped and an empty one takes its place.
+// A key is found for at least window - window / generations inserts after it
+// and never after window more, so more generations forget more precisely at
+// the cost of checking more filters on every lookup.
+pub struct RotatingBloomFilter<T: ?Sized> {
+    // Oldest first.
+    generations: VecDeque<BloomFilter<T>>,
+    generation_size: usize,
+    generation_fp_rate: f64,
+    // Inserts into the newest generation so far.
+    items: usize,
+}
+
+impl<T: ?Sized> RotatingBloomFilter<T> {
+    // fp_rate is for the whole filter. A lookup checks every generation, so
+    // each one gets 1 - (1 - fp_rate)^(1 / generations).
+    pub fn new(window: usize, generations: usize, fp_rate: f64) -> RotatingBloomFilter<T> {
+        assert!(generations > 0, "need at least one generation");
+        let generation_size = window.div_ceil(generations).max(1);
+        let generation_fp_rate = 1.0 - (1.0 - fp_rate).powf(1.0 / generations as f64);
+        RotatingBloomFilter {
+            generations: (0..generations)
+                .map(|_| BloomFilter::new(generation_size, generation_fp_rate))
+                .collect(),
+            generation_size,
+            generation_fp_rate,
+            items: 0,
+        }
+    }
+
+    // Drops the oldest generation and starts a new one.
+    fn rotate(&mut self) {
+        self.generations.pop_front();
+        self.generations.push_back(BloomFilter::new(
+            self.generation_size,
+            self.generation_fp_rate,
+        ));
+        self.items = 0;
+    }
+
+    // Every insert counts towards the window, including repeats, which
+    // refresh the key.
+    pub fn insert_bytes(&mut self, value: &[u8]) {
+        if self.items == self.generation_size {
+            self.rotate();
+        }
+        self.generations.back_mut().unwrap().insert_bytes(value);
+        self.items += 1;
+    }
+
+    pub fn get_bytes(&self, value: &[u8]) -> bool {
+        self.generations
+            .iter()
+            .rev()
+            .any(|generation| generation.get_bytes(value))
+    }
+
+    // Inserts a key is remembered for at most.
+    pub fn window(&self) -> usize {
+        self.generation_size * self.generations.len()
+    }
+
+    pub fn generations(&self) -> usize {
+        self.generations.len()
+    }
+}
+
+impl<T: Hash + ?Sized> RotatingBloomFilter<T> {
+    pub fn insert(&mut self, value: &T) {
+        self.insert_bytes(&KeyBytes::of(value));
+    }
+
+    pub fn get(&self, value: &T) -> bool {
+        self.get_bytes(&KeyBytes::of(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert() {
+        let mut filter = RotatingBloomFilter::new(100, 4, 0.001);
+        filter.insert("test");
+        assert!(!filter.get("bar"));
+        assert!(!filter.get("foo"));
+        assert!(filter.get("test"));
+    }
+
+    #[test]
+    fn test_window() {
+        let mut filter = RotatingBloomFilter::<u64>::new(1000, 4, 0.01);
+        assert_eq!(filter.window(), 1000);
+        assert_eq!(filter.generations(), 4);
+        for i in 0..5000 {
+            filter.insert(&i);
+        }
+        // The last window - window / generations keys are all remembered.
+        for i in 4250..5000 {
+            assert!(filter.get(&i));
+        }
+        // Anything older than the window is gone, bar false positives.
+        let old = (0..4000).filter(|i| filter.get(i)).count();
+        assert!((old as f64 / 4000.0) < 0.02);
+    }
+
+    #[test]
+    fn test_refresh() {
+        let mut filter = RotatingBloomFilter::<u64>::new(100, 2, 0.001);
+        filter.insert(&0);
+        for i in 1..1000 {
+            // Re-inserting keeps the key alive however long the stream.
+            if i % 40 == 0 {
+                filter.insert(&0);
+            }
+            filter.insert(&(i + 1_000_000));
+        }
+        assert!(filter.get(&0));
+    }
+
+    #[test]
+    fn test_fp_rate() {
+        let mut filter = RotatingBloomFilter::<u64>::new(10_000, 5, 0.01);
+        for i in 0..50_000 {
+            filter.insert(&i);
+        }
+        let false_positives = (100_000..300_000).filter(|i| filter.get(i)).count();
+        assert!((false_positives as f64 / 200_000.0) < 0.012);
+    }
+}
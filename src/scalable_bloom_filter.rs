@@ -0,0 +1,195 @@
+use crate::bloom_filter::{BloomFilter, KeyBytes};
+use std::hash::Hash;
+
+// Defaults from Almeida et al. "Scalable Bloom Filters": each stage holds
+// twice as many items as the previous one and has 0.9 times its error rate.
+pub const DEFAULT_GROWTH: usize = 2;
+pub const DEFAULT_TIGHTENING_RATIO: f64 = 0.9;
+// Most items a stage added by growth holds, so repeated growth can't
+// overflow the capacity. Stages past it are all added at this size.
+pub const MAX_STAGE_CAPACITY: usize = u32::MAX as usize;
+
+struct Stage<T: ?Sized> {
+    filter: BloomFilter<T>,
+    capacity: usize,
+    items: usize,
+}
+
+// ScalableBloomFilter chains BloomFilter stages. When the newest stage is full
+// a larger one is added with a tighter error rate. The stage error rates form
+// a geometric series p0, p0 * r, p0 * r^2, ... whose sum p0 / (1 - r) is kept
+// at the requested fp_rate however many stages get added.
+pub struct ScalableBloomFilter<T: ?Sized> {
+    stages: Vec<Stage<T>>,
+    fp_rate: f64,
+    growth: usize,
+    tightening_ratio: f64,
+}
+
+impl<T: ?Sized> ScalableBloomFilter<T> {
+    pub fn new(size: usize, fp_rate: f64) -> ScalableBloomFilter<T> {
+        ScalableBloomFilter::with_growth(size, fp_rate, DEFAULT_GROWTH, DEFAULT_TIGHTENING_RATIO)
+    }
+
+    // size is the capacity of the first stage, every later stage is `growth`
+    // times bigger than the one before it. growth must be at least 1 and
+    // tightening_ratio in (0, 1), or this panics.
+    pub fn with_growth(
+        size: usize,
+        fp_rate: f64,
+        growth: usize,
+        tightening_ratio: f64,
+    ) -> ScalableBloomFilter<T> {
+        assert!(growth >= 1, "growth must be at least 1, got {}", growth);
+        assert!(
+            tightening_ratio > 0.0 && tightening_ratio < 1.0,
+            "tightening ratio must be between 0 and 1 exclusive, got {}",
+            tightening_ratio
+        );
+        let mut filter = ScalableBloomFilter {
+            stages: Vec::new(),
+            fp_rate,
+            growth,
+            tightening_ratio,
+        };
+        filter.add_stage(size);
+        filter
+    }
+
+    fn add_stage(&mut self, capacity: usize) {
+        let stage_fp_rate = self.fp_rate
+            * (1.0 - self.tightening_ratio)
+            * self.tightening_ratio.powi(self.stages.len() as i32);
+        self.stages.push(Stage {
+            filter: BloomFilter::new(capacity, stage_fp_rate),
+            capacity,
+            items: 0,
+        });
+    }
+
+    fn next_capacity(&self) -> usize {
+        let last = self.stages.last().unwrap();
+        last.capacity
+            .saturating_mul(self.growth)
+            .min(MAX_STAGE_CAPACITY)
+    }
+
+    pub fn insert_bytes(&mut self, value: &[u8]) {
+        // Re-inserting a known key would only eat into the capacity.
+        if self.get_bytes(value) {
+            return;
+        }
+        let last = self.stages.last().unwrap();
+        if last.items >= last.capacity {
+            self.add_stage(self.next_capacity());
+        }
+        let last = self.stages.last_mut().unwrap();
+        last.filter.insert_bytes(value);
+        last.items += 1;
+    }
+
+    pub fn get_bytes(&self, value: &[u8]) -> bool {
+        self.stages
+            .iter()
+            .any(|stage| stage.filter.get_bytes(value))
+    }
+
+    // Number of distinct items inserted, up to false positives on insert.
+    pub fn len(&self) -> usize {
+        self.stages.iter().map(|stage| stage.items).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Items the current stages can hold before another one is added.
+    pub fn capacity(&self) -> usize {
+        self.stages
+            .iter()
+            .fold(0, |sum, stage| sum.saturating_add(stage.capacity))
+    }
+
+    pub fn num_stages(&self) -> usize {
+        self.stages.len()
+    }
+
+    // Upper bound on the false positive rate of the stages added so far.
+    pub fn max_fp_rate(&self) -> f64 {
+        1.0 - (0..self.stages.len())
+            .map(|i| {
+                1.0 - self.fp_rate
+                    * (1.0 - self.tightening_ratio)
+                    * self.tightening_ratio.powi(i as i32)
+            })
+            .product::<f64>()
+    }
+}
+
+impl<T: Hash + ?Sized> ScalableBloomFilter<T> {
+    pub fn insert(&mut self, value: &T) {
+        self.insert_bytes(&KeyBytes::of(value));
+    }
+
+    pub fn get(&self, value: &T) -> bool {
+        self.get_bytes(&KeyBytes::of(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert() {
+        let mut filter = ScalableBloomFilter::new(2, 0.001);
+        filter.insert("test");
+        filter.insert("test");
+        assert!(filter.get("test"));
+        assert!(!filter.get("bar"));
+        assert_eq!(filter.len(), 1);
+    }
+
+    #[test]
+    fn test_grows_past_capacity() {
+        let mut filter = ScalableBloomFilter::<u64>::new(100, 0.01);
+        for i in 0..10_000 {
+            filter.insert(&i);
+        }
+        assert!(filter.num_stages() > 1);
+        assert!(filter.capacity() >= filter.len());
+        for i in 0..10_000 {
+            assert!(filter.get(&i));
+        }
+        assert!(filter.max_fp_rate() <= 0.01);
+
+        let false_positives = (10_000..110_000u64).filter(|i| filter.get(i)).count();
+        assert!((false_positives as f64 / 100_000.0) < 0.01);
+    }
+
+    #[test]
+    fn test_stage_capacity_is_capped() {
+        let mut filter = ScalableBloomFilter::<u64>::with_growth(100, 0.01, 1000, 0.5);
+        assert_eq!(filter.next_capacity(), 100_000);
+        filter.stages[0].capacity = usize::MAX / 2 + 1;
+        assert_eq!(filter.next_capacity(), MAX_STAGE_CAPACITY);
+    }
+
+    #[test]
+    #[should_panic(expected = "growth must be at least 1")]
+    fn test_zero_growth_panics() {
+        ScalableBloomFilter::<u64>::with_growth(100, 0.01, 0, DEFAULT_TIGHTENING_RATIO);
+    }
+
+    #[test]
+    #[should_panic(expected = "tightening ratio must be between 0 and 1 exclusive")]
+    fn test_tightening_ratio_of_one_panics() {
+        ScalableBloomFilter::<u64>::with_growth(100, 0.01, DEFAULT_GROWTH, 1.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "tightening ratio must be between 0 and 1 exclusive")]
+    fn test_nan_tightening_ratio_panics() {
+        ScalableBloomFilter::<u64>::with_growth(100, 0.01, DEFAULT_GROWTH, f64::NAN);
+    }
+}
@@ -0,0 +1,145 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+// Everything the crate saves starts with a 4 byte magic number and a little
+// endian u16 version, and carries a crc32 so corrupt input is rejected
+// instead of silently answering queries wrong.
+
+// Why a saved structure could not be loaded.
+#[derive(Debug)]
+pub enum DecodeError {
+    Io(io::Error),
+    // The input ended before the structure did.
+    Truncated,
+    // The input does not start with the expected magic number.
+    BadMagic,
+    UnsupportedVersion(u16),
+    UnknownHashStrategy(u8),
+    ChecksumMismatch { expected: u32, found: u32 },
+    // The header or payload holds values no valid structure can have.
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Io(err) => write!(f, "i/o error: {}", err),
+            DecodeError::Truncated => write!(f, "input is truncated"),
+            DecodeError::BadMagic => write!(f, "bad magic number"),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported format version {}", version)
+            }
+            DecodeError::UnknownHashStrategy(strategy) => {
+                write!(f, "unknown hash strategy {}", strategy)
+            }
+            DecodeError::ChecksumMismatch { expected, found } => write!(
+                f,
+                "checksum mismatch: expected {:#010x}, found {:#010x}",
+                expected, found
+            ),
+            DecodeError::Corrupt(reason) => write!(f, "corrupt input: {}", reason),
+        }
+    }
+}
+
+impl Error for DecodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DecodeError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DecodeError {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            DecodeError::Truncated
+        } else {
+            DecodeError::Io(err)
+        }
+    }
+}
+
+pub(crate) fn read_array<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N], DecodeError> {
+    let mut buf = [0; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+// Reads `count` little endian u64 words, feeding their bytes to `crc`. The
+// words are read in chunks and the buffer only grows as data arrives, so a
+// corrupt length can't force a huge allocation up front.
+pub(crate) fn read_words<R: Read>(
+    reader: &mut R,
+    count: usize,
+    crc: &mut crc32fast::Hasher,
+) -> Result<Vec<u64>, DecodeError> {
+    const CHUNK_WORDS: usize = 1024;
+    let mut words = Vec::with_capacity(count.min(CHUNK_WORDS));
+    let mut buf = [0; CHUNK_WORDS * 8];
+    while words.len() < count {
+        let chunk = (count - words.len()).min(CHUNK_WORDS);
+        let bytes = &mut buf[..chunk * 8];
+        reader.read_exact(bytes)?;
+        crc.update(bytes);
+        words.extend(
+            bytes
+                .chunks_exact(8)
+                .map(|word| u64::from_le_bytes(word.try_into().unwrap())),
+        );
+    }
+    Ok(words)
+}
+
+// Reads `len` raw bytes, feeding them to `crc`, in chunks like `read_words`.
+pub(crate) fn read_bytes<R: Read>(
+    reader: &mut R,
+    len: usize,
+    crc: &mut crc32fast::Hasher,
+) -> Result<Vec<u8>, DecodeError> {
+    const CHUNK: usize = 8192;
+    let mut bytes = Vec::with_capacity(len.min(CHUNK));
+    let mut buf = [0; CHUNK];
+    while bytes.len() < len {
+        let chunk = &mut buf[..(len - bytes.len()).min(CHUNK)];
+        reader.read_exact(chunk)?;
+        crc.update(chunk);
+        bytes.extend_from_slice(chunk);
+    }
+    Ok(bytes)
+}
+
+// Writes `words` little endian, the layout `read_words` expects.
+pub(crate) fn write_words<W: Write>(writer: &mut W, words: &[u64]) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(words.len().min(1024) * 8);
+    for chunk in words.chunks(1024) {
+        buf.clear();
+        for word in chunk {
+            buf.extend_from_slice(&word.to_le_bytes());
+        }
+        writer.write_all(&buf)?;
+    }
+    Ok(())
+}
+
+// crc32 of `words` in their little endian on-disk form, continuing `crc`.
+pub(crate) fn checksum_words(crc: &mut crc32fast::Hasher, words: &[u64]) {
+    for word in words {
+        crc.update(&word.to_le_bytes());
+    }
+}
+
+// Reads a whole structure from `bytes` with `read`, rejecting anything left
+// over afterwards.
+pub(crate) fn from_slice<T, F>(mut bytes: &[u8], read: F) -> Result<T, DecodeError>
+where
+    F: FnOnce(&mut &[u8]) -> Result<T, DecodeError>,
+{
+    let value = read(&mut bytes)?;
+    if !bytes.is_empty() {
+        return Err(DecodeError::Corrupt("trailing bytes after the payload"));
+    }
+    Ok(value)
+}
@@ -0,0 +1,247 @@
+use crate::bloom_filter::KeyBytes;
+use crate::bloom_hasher::{BloomHasher, Murmur3};
+use bitvec::prelude::*;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+// Bits per cell unless asked otherwise. With one bit a cell is either set or
+// cleared, the variant Deng and Rafiei analyse the most.
+pub const DEFAULT_CELL_BITS: usize = 1;
+
+// Hash functions for a target false positive rate, as for a BloomFilter
+// filled to half its bits.
+fn num_hashes(fp_rate: f64) -> usize {
+    ((1.0 / fp_rate).log2().ceil() as usize).max(1)
+}
+
+// Cells to decrement per insert so the filter settles at `fp_rate`. Solves
+// the stable point equation of Deng and Rafiei, "Approximately Detecting
+// Duplicates for Streaming Data using Stable Bloom Filters", for P, rounding
+// up so the rate settles at or below the target.
+pub fn num_decrements(cells: usize, hashes: usize, cell_bits: usize, fp_rate: f64) -> usize {
+    let max = ((1u32 << cell_bits) - 1) as f64;
+    let sub = (1.0 - fp_rate.powf(1.0 / hashes as f64)).powf(1.0 / max);
+    let denom = (1.0 / sub - 1.0) * (1.0 / hashes as f64 - 1.0 / cells as f64);
+    ((1.0 / denom).ceil() as usize).max(1)
+}
+
+// StableBloomFilter is meant for unbounded streams, where a BloomFilter would
+// end up with every bit set. Cells hold a small counter instead of a bit: an
+// insert first decrements P randomly chosen cells and then sets the key's k
+// cells to the maximum. Old keys fade out, the fraction of zero cells
+// converges to a stable point whatever the length of the stream, and so does
+// the false positive rate. The price is false negatives: a key inserted long
+// enough ago may no longer be found.
+pub struct StableBloomFilter<T: ?Sized> {
+    cells: BitVec<u64, Lsb0>,
+    len: usize,
+    cell_bits: usize,
+    hashes: usize,
+    decrements: usize,
+    rng: u64,
+    _key: PhantomData<fn(&T)>,
+}
+
+impl<T: ?Sized> StableBloomFilter<T> {
+    // A filter of `cells` cells that settles at `fp_rate`.
+    pub fn new(cells: usize, fp_rate: f64) -> StableBloomFilter<T> {
+        StableBloomFilter::with_cell_bits(cells, DEFAULT_CELL_BITS, fp_rate)
+    }
+
+    // More bits per cell let keys survive more decrements. cell_bits must be
+    // between 1 and 8, cells positive and fp_rate in (0, 1).
+    pub fn with_cell_bits(cells: usize, cell_bits: usize, fp_rate: f64) -> StableBloomFilter<T> {
+        assert!(
+            (1..=8).contains(&cell_bits),
+            "cell_bits must be between 1 and 8"
+        );
+        assert!(cells > 0, "cells must be positive");
+        assert!(
+            fp_rate > 0.0 && fp_rate < 1.0,
+            "false positive rate must be between 0 and 1 exclusive, got {}",
+            fp_rate
+        );
+        let k = num_hashes(fp_rate).min(cells);
+        StableBloomFilter {
+            cells: bitvec![u64, Lsb0; 0; cells * cell_bits],
+            len: cells,
+            cell_bits,
+            hashes: k,
+            decrements: num_decrements(cells, k, cell_bits, fp_rate).min(cells),
+            rng: 0x2545_f491_4f6c_dd1d,
+            _key: PhantomData,
+        }
+    }
+
+    #[inline]
+    fn max(&self) -> u8 {
+        ((1u16 << self.cell_bits) - 1) as u8
+    }
+
+    #[inline]
+    fn cell(&self, i: usize) -> u8 {
+        self.cells[i * self.cell_bits..(i + 1) * self.cell_bits].load_le()
+    }
+
+    #[inline]
+    fn set_cell(&mut self, i: usize, value: u8) {
+        let bits = self.cell_bits;
+        self.cells[i * bits..(i + 1) * bits].store_le(value);
+    }
+
+    fn next_random(&mut self) -> usize {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng as usize
+    }
+
+    // Decrements P cells picked independently at random, as the stable point
+    // analysis assumes. A cell may be picked twice.
+    fn decrement(&mut self) {
+        for _ in 0..self.decrements {
+            let i = self.next_random() % self.len;
+            let value = self.cell(i);
+            if value > 0 {
+                self.set_cell(i, value - 1);
+            }
+        }
+    }
+
+    pub fn insert_bytes(&mut self, value: &[u8]) {
+        self.decrement();
+        let max = self.max();
+        let mut cells = Vec::with_capacity(self.hashes);
+        Murmur3.for_each_index(value, self.hashes, self.len, |i| {
+            cells.push(i);
+            true
+        });
+        for i in cells {
+            self.set_cell(i, max);
+        }
+    }
+
+    pub fn get_bytes(&self, value: &[u8]) -> bool {
+        Murmur3.for_each_index(value, self.hashes, self.len, |i| self.cell(i) > 0)
+    }
+
+    // Number of cells in the filter.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn hashes(&self) -> usize {
+        self.hashes
+    }
+
+    // Cells decremented by every insert.
+    pub fn decrements(&self) -> usize {
+        self.decrements
+    }
+
+    pub fn cell_bits(&self) -> usize {
+        self.cell_bits
+    }
+
+    // Fraction of cells that are zero once the filter has seen a long
+    // stream of distinct keys.
+    pub fn stable_point(&self) -> f64 {
+        let max = self.max() as f64;
+        let per_insert =
+            self.decrements as f64 * (1.0 / self.hashes as f64 - 1.0 / self.len as f64);
+        (1.0 / (1.0 + 1.0 / per_insert)).powf(max)
+    }
+
+    // False positive rate at the stable point.
+    pub fn fp_rate(&self) -> f64 {
+        (1.0 - self.stable_point()).powi(self.hashes as i32)
+    }
+
+    // Fraction of cells that are not zero right now.
+    pub fn fill_ratio(&self) -> f64 {
+        (0..self.len).filter(|&i| self.cell(i) > 0).count() as f64 / self.len as f64
+    }
+}
+
+impl<T: Hash + ?Sized> StableBloomFilter<T> {
+    pub fn insert(&mut self, value: &T) {
+        self.insert_bytes(&KeyBytes::of(value));
+    }
+
+    pub fn get(&self, value: &T) -> bool {
+        self.get_bytes(&KeyBytes::of(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert() {
+        let mut filter = StableBloomFilter::new(1000, 0.01);
+        filter.insert("test");
+        assert!(!filter.get("bar"));
+        assert!(!filter.get("foo"));
+        assert!(filter.get("test"));
+    }
+
+    #[test]
+    fn test_parameters() {
+        let filter = StableBloomFilter::<u64>::new(10_000, 0.01);
+        assert_eq!(filter.hashes(), 7);
+        assert!(filter.decrements() >= 1);
+        assert!(filter.fp_rate() <= 0.01 && filter.fp_rate() > 0.005);
+
+        let wide = StableBloomFilter::<u64>::with_cell_bits(10_000, 3, 0.01);
+        assert_eq!(wide.cell_bits(), 3);
+        assert!(wide.decrements() > filter.decrements());
+        assert!(wide.fp_rate() <= 0.01 && wide.fp_rate() > 0.005);
+    }
+
+    #[test]
+    fn test_unbounded_stream() {
+        // A stream twenty times longer than the filter has cells.
+        let mut filter = StableBloomFilter::<u64>::with_cell_bits(10_000, 2, 0.01);
+        for i in 0..200_000 {
+            filter.insert(&i);
+            // The newest key is always found.
+            assert!(filter.get(&i));
+        }
+        let fill = filter.fill_ratio();
+        assert!(
+            (fill - (1.0 - filter.stable_point())).abs() < 0.05,
+            "fill {}",
+            fill
+        );
+        assert!(fill < 0.9);
+
+        let probes = 100_000;
+        let false_positives = (1_000_000..1_000_000 + probes)
+            .filter(|i| filter.get(i))
+            .count();
+        assert!((false_positives as f64 / probes as f64) < 0.02);
+
+        // Recent keys are mostly still there, old ones are mostly gone.
+        let recent = (199_900..200_000).filter(|i| filter.get(i)).count();
+        let old = (0..1000).filter(|i| filter.get(i)).count();
+        assert!(recent > 90, "recent {}", recent);
+        assert!(old < 50, "old {}", old);
+    }
+
+    #[test]
+    #[should_panic(expected = "cells must be positive")]
+    fn test_zero_cells_panics() {
+        StableBloomFilter::<u64>::new(0, 0.01);
+    }
+
+    #[test]
+    #[should_panic(expected = "false positive rate must be between 0 and 1")]
+    fn test_bad_fp_rate_panics() {
+        StableBloomFilter::<u64>::new(100, f64::NAN);
+    }
+}
@@ -0,0 +1,474 @@
+use crate::bloom_filter::KeyBytes;
+use crate::bloom_hasher::double_hash;
+use crate::serialization::{from_slice, read_array, read_bytes, DecodeError};
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::ops::BitXor;
+
+// How many seeds construction tries before giving up. With distinct keys a
+// seed fails with probability well under 1%, so running out means the key
+// hashes themselves collide.
+pub const MAX_ATTEMPTS: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConstructionFailed;
+
+impl fmt::Display for ConstructionFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no seed out of {} could peel the key set", MAX_ATTEMPTS)
+    }
+}
+
+impl Error for ConstructionFailed {}
+
+// The integer types static filters can store as fingerprints.
+pub trait Fingerprint: Copy + Default + Eq + BitXor<Output = Self> + fmt::Debug {
+    const BITS: u8;
+
+    fn from_hash(hash: u64) -> Self;
+
+    fn extend_le(self, out: &mut Vec<u8>);
+
+    fn from_le(bytes: &[u8]) -> Self;
+}
+
+impl Fingerprint for u8 {
+    const BITS: u8 = 8;
+
+    fn from_hash(hash: u64) -> Self {
+        (hash ^ (hash >> 32)) as u8
+    }
+
+    fn extend_le(self, out: &mut Vec<u8>) {
+        out.push(self);
+    }
+
+    fn from_le(bytes: &[u8]) -> Self {
+        bytes[0]
+    }
+}
+
+impl Fingerprint for u16 {
+    const BITS: u8 = 16;
+
+    fn from_hash(hash: u64) -> Self {
+        (hash ^ (hash >> 32)) as u16
+    }
+
+    fn extend_le(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn from_le(bytes: &[u8]) -> Self {
+        u16::from_le_bytes([bytes[0], bytes[1]])
+    }
+}
+
+// The seed independent hash of a key, the low half of the 128 bit murmur3
+// hash the bloom filters use.
+pub(crate) fn key_hash(value: &[u8]) -> u64 {
+    double_hash(value).0
+}
+
+// murmur3's 64 bit finalizer over hash + seed. It is a bijection, so distinct
+// key hashes stay distinct under every seed.
+#[inline]
+pub(crate) fn mix(hash: u64, seed: u64) -> u64 {
+    let mut h = hash.wrapping_add(seed);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^ (h >> 33)
+}
+
+// splitmix64, used to draw a fresh seed for every construction attempt.
+pub(crate) fn next_seed(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// Finds an order in which every hash can be given a slot of its own: a slot
+// only one remaining hash maps to is peeled off together with that hash,
+// which may leave other slots with a single hash. Returns the peeled
+// (hash, slot) pairs, or None when some slots are never left with one hash.
+// The three positions of a hash must be distinct.
+pub(crate) fn peel<P>(hashes: &[u64], array_len: usize, positions: P) -> Option<Vec<(u64, usize)>>
+where
+    P: Fn(u64) -> [usize; 3],
+{
+    let mut count = vec![0u32; array_len];
+    let mut xors = vec![0u64; array_len];
+    for &hash in hashes {
+        for slot in positions(hash) {
+            count[slot] += 1;
+            xors[slot] ^= hash;
+        }
+    }
+    let mut queue: Vec<usize> = (0..array_len).filter(|&slot| count[slot] == 1).collect();
+    let mut stack = Vec::with_capacity(hashes.len());
+    while let Some(slot) = queue.pop() {
+        if count[slot] != 1 {
+            continue;
+        }
+        // The only hash left in the slot is the xor of everything in it.
+        let hash = xors[slot];
+        stack.push((hash, slot));
+        for other in positions(hash) {
+            count[other] -= 1;
+            xors[other] ^= hash;
+            if count[other] == 1 {
+                queue.push(other);
+            }
+        }
+    }
+    (stack.len() == hashes.len()).then_some(stack)
+}
+
+// Fills the fingerprint array in reverse peeling order so that for every key
+// the xor of the fingerprints at its three positions is its own fingerprint.
+pub(crate) fn assign<F, P>(stack: &[(u64, usize)], array_len: usize, positions: P) -> Vec<F>
+where
+    F: Fingerprint,
+    P: Fn(u64) -> [usize; 3],
+{
+    let mut fingerprints = vec![F::default(); array_len];
+    for &(hash, slot) in stack.iter().rev() {
+        let mut fingerprint = F::from_hash(hash);
+        for other in positions(hash) {
+            if other != slot {
+                fingerprint = fingerprint ^ fingerprints[other];
+            }
+        }
+        fingerprints[slot] = fingerprint;
+    }
+    fingerprints
+}
+
+// Static filters are saved like BloomFilter, all integers little endian:
+//
+//   offset  size  field
+//        0     4  magic, b"XORF" for XorFilter, b"BFUF" for BinaryFuseFilter
+//        4     2  format version, currently 1
+//        6     1  fingerprint bits, 8 or 16
+//        7     1  reserved, zero
+//        8     8  seed
+//       16     8  layout, the block length of an XorFilter or the segment
+//                 length (low half) and segment count (high half) of a
+//                 BinaryFuseFilter
+//       24     4  crc32 of bytes 0..24 followed by the payload
+//       28     4  reserved, zero
+//       32     -  payload: the fingerprints, little endian
+pub const VERSION: u16 = 1;
+pub const HEADER_LEN: usize = 32;
+
+pub(crate) fn write_fingerprints<W: Write, F: Fingerprint>(
+    mut writer: W,
+    magic: [u8; 4],
+    seed: u64,
+    layout: u64,
+    fingerprints: &[F],
+) -> io::Result<()> {
+    let mut payload = Vec::with_capacity(fingerprints.len() * F::BITS as usize / 8);
+    for fingerprint in fingerprints {
+        fingerprint.extend_le(&mut payload);
+    }
+    let mut header = [0; HEADER_LEN];
+    header[0..4].copy_from_slice(&magic);
+    header[4..6].copy_from_slice(&VERSION.to_le_bytes());
+    header[6] = F::BITS;
+    header[8..16].copy_from_slice(&seed.to_le_bytes());
+    header[16..24].copy_from_slice(&layout.to_le_bytes());
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(&header[..24]);
+    crc.update(&payload);
+    header[24..28].copy_from_slice(&crc.finalize().to_le_bytes());
+    writer.write_all(&header)?;
+    writer.write_all(&payload)
+}
+
+// Reads what write_fingerprints wrote. `array_len` checks the layout field
+// and turns it into the number of fingerprints to read.
+pub(crate) fn read_fingerprints<R, F, L>(
+    mut reader: R,
+    magic: [u8; 4],
+    array_len: L,
+) -> Result<(u64, u64, Vec<F>), DecodeError>
+where
+    R: Read,
+    F: Fingerprint,
+    L: FnOnce(u64) -> Result<usize, DecodeError>,
+{
+    let header: [u8; HEADER_LEN] = read_array(&mut reader)?;
+    if header[0..4] != magic {
+        return Err(DecodeError::BadMagic);
+    }
+    let version = u16::from_le_bytes([header[4], header[5]]);
+    if version != VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+    if header[6] != F::BITS {
+        return Err(DecodeError::Corrupt("fingerprint width does not match"));
+    }
+    if header[7] != 0 || header[28..32] != [0; 4] {
+        return Err(DecodeError::Corrupt("reserved header bytes are not zero"));
+    }
+    let seed = u64::from_le_bytes(header[8..16].try_into().unwrap());
+    let layout = u64::from_le_bytes(header[16..24].try_into().unwrap());
+    let expected = u32::from_le_bytes(header[24..28].try_into().unwrap());
+    let len = array_len(layout)?;
+    let width = F::BITS as usize / 8;
+    let byte_len = len.checked_mul(width).ok_or(DecodeError::Corrupt(
+        "fingerprint array does not fit in usize",
+    ))?;
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(&header[..24]);
+    let payload = read_bytes(&mut reader, byte_len, &mut crc)?;
+    let found = crc.finalize();
+    if found != expected {
+        return Err(DecodeError::ChecksumMismatch { expected, found });
+    }
+    let fingerprints = payload.chunks_exact(width).map(F::from_le).collect();
+    Ok((seed, layout, fingerprints))
+}
+
+pub const MAGIC: [u8; 4] = *b"XORF";
+
+// XorFilter is a static filter (Graf and Lemire, "Xor Filters: Faster and
+// Smaller Than Bloom and Cuckoo Filters"). Each key maps to one slot in each
+// third of a fingerprint array, and the array is filled so the three slots
+// xor to the key's fingerprint. It takes about 1.23 * F::BITS bits per key
+// for a false positive rate of 2^-F::BITS, against 1.44 * log2(1 / p) bits
+// for a BloomFilter. Keys can't be added once it is built.
+pub struct XorFilter<T: ?Sized, F: Fingerprint = u8> {
+    seed: u64,
+    block_length: usize,
+    fingerprints: Vec<F>,
+    _key: PhantomData<fn(&T)>,
+}
+
+pub type Xor8<T> = XorFilter<T, u8>;
+pub type Xor16<T> = XorFilter<T, u16>;
+
+#[inline]
+fn reduce(hash: u32, n: usize) -> usize {
+    ((hash as u64 * n as u64) >> 32) as usize
+}
+
+#[inline]
+fn xor_positions(hash: u64, block_length: usize) -> [usize; 3] {
+    [
+        reduce(hash as u32, block_length),
+        reduce(hash.rotate_left(21) as u32, block_length) + block_length,
+        reduce(hash.rotate_left(42) as u32, block_length) + 2 * block_length,
+    ]
+}
+
+impl<T: ?Sized, F: Fingerprint> XorFilter<T, F> {
+    // Builds a filter from raw byte keys, hashed like BloomFilter::insert_bytes.
+    pub fn from_byte_keys<'a, I>(keys: I) -> Result<XorFilter<T, F>, ConstructionFailed>
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        XorFilter::from_hashes(keys.into_iter().map(key_hash).collect())
+    }
+
+    fn from_hashes(mut hashes: Vec<u64>) -> Result<XorFilter<T, F>, ConstructionFailed> {
+        // Duplicate keys can never be peeled apart.
+        hashes.sort_unstable();
+        hashes.dedup();
+        let block_length = (32 + (1.23 * hashes.len() as f64).ceil() as usize) / 3;
+        let array_len = 3 * block_length;
+        let mut state = 0;
+        for _ in 0..MAX_ATTEMPTS {
+            let seed = next_seed(&mut state);
+            let seeded: Vec<u64> = hashes.iter().map(|&hash| mix(hash, seed)).collect();
+            let positions = |hash| xor_positions(hash, block_length);
+            if let Some(stack) = peel(&seeded, array_len, positions) {
+                return Ok(XorFilter {
+                    seed,
+                    block_length,
+                    fingerprints: assign(&stack, array_len, positions),
+                    _key: PhantomData,
+                });
+            }
+        }
+        Err(ConstructionFailed)
+    }
+
+    pub fn contains_bytes(&self, value: &[u8]) -> bool {
+        let hash = mix(key_hash(value), self.seed);
+        let [h0, h1, h2] = xor_positions(hash, self.block_length);
+        F::from_hash(hash) == self.fingerprints[h0] ^ self.fingerprints[h1] ^ self.fingerprints[h2]
+    }
+
+    // Number of fingerprints in the array.
+    pub fn len(&self) -> usize {
+        self.fingerprints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fingerprints.is_empty()
+    }
+
+    pub fn size_in_bytes(&self) -> usize {
+        self.fingerprints.len() * F::BITS as usize / 8
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.size_in_bytes());
+        self.write_to(&mut bytes)
+            .expect("writing to a Vec never fails");
+        bytes
+    }
+
+    pub fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        write_fingerprints(
+            writer,
+            MAGIC,
+            self.seed,
+            self.block_length as u64,
+            &self.fingerprints,
+        )
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<XorFilter<T, F>, DecodeError> {
+        from_slice(bytes, |reader| XorFilter::read_from(reader))
+    }
+
+    pub fn read_from<R: Read>(reader: R) -> Result<XorFilter<T, F>, DecodeError> {
+        let (seed, block_length, fingerprints) = read_fingerprints(reader, MAGIC, |layout| {
+            usize::try_from(layout)
+                .ok()
+                .filter(|&block_length| block_length > 0)
+                .and_then(|block_length| block_length.checked_mul(3))
+                .ok_or(DecodeError::Corrupt("invalid block length"))
+        })?;
+        Ok(XorFilter {
+            seed,
+            block_length: block_length as usize,
+            fingerprints,
+            _key: PhantomData,
+        })
+    }
+}
+
+impl<T: Hash + ?Sized, F: Fingerprint> XorFilter<T, F> {
+    pub fn from_keys<'a, I>(keys: I) -> Result<XorFilter<T, F>, ConstructionFailed>
+    where
+        I: IntoIterator<Item = &'a T>,
+        T: 'a,
+    {
+        XorFilter::from_hashes(
+            keys.into_iter()
+                .map(|key| key_hash(&KeyBytes::of(key)))
+                .collect(),
+        )
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.contains_bytes(&KeyBytes::of(value))
+    }
+}
+
+impl<T: Hash, F: Fingerprint> XorFilter<T, F> {
+    pub fn from_slice(keys: &[T]) -> Result<XorFilter<T, F>, ConstructionFailed> {
+        XorFilter::from_keys(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fp_rate<F: Fingerprint>(filter: &XorFilter<u64, F>, start: u64) -> f64 {
+        let probes = 200_000;
+        let false_positives = (start..start + probes)
+            .filter(|i| filter.contains(i))
+            .count();
+        false_positives as f64 / probes as f64
+    }
+
+    #[test]
+    fn test_contains() {
+        let filter = Xor8::<str>::from_keys(["test", "foo"]).unwrap();
+        assert!(filter.contains("test"));
+        assert!(filter.contains("foo"));
+        assert!(!filter.contains("bar"));
+        assert!(!filter.contains("bazz"));
+    }
+
+    #[test]
+    fn test_xor8() {
+        let keys: Vec<u64> = (0..10_000).collect();
+        let filter = Xor8::from_slice(&keys).unwrap();
+        assert!(keys.iter().all(|key| filter.contains(key)));
+        // 2^-8 is about 0.0039.
+        assert!(fp_rate(&filter, 10_000) < 0.005);
+        assert!((filter.size_in_bytes() as f64 / keys.len() as f64) < 1.25);
+    }
+
+    #[test]
+    fn test_xor16() {
+        let keys: Vec<u64> = (0..10_000).collect();
+        let filter = Xor16::from_keys(keys.iter()).unwrap();
+        assert!(keys.iter().all(|key| filter.contains(key)));
+        assert!(fp_rate(&filter, 10_000) < 0.0002);
+    }
+
+    #[test]
+    fn test_duplicates_and_empty() {
+        let keys = [1u64, 2, 3, 1, 2, 3, 3];
+        let filter = Xor8::from_slice(&keys).unwrap();
+        assert!(keys.iter().all(|key| filter.contains(key)));
+
+        let empty = Xor8::<u64>::from_slice(&[]).unwrap();
+        assert!(fp_rate(&empty, 0) < 0.01);
+    }
+
+    #[test]
+    fn test_byte_keys() {
+        let filter = Xor16::<[u8]>::from_byte_keys([b"test".as_slice(), b"foo"]).unwrap();
+        assert!(filter.contains_bytes(b"test"));
+        assert!(filter.contains_bytes(b"foo"));
+        assert!(!filter.contains_bytes(b"bar"));
+    }
+
+    #[test]
+    fn test_serialization() {
+        let keys: Vec<u64> = (0..1000).collect();
+        let filter = Xor16::from_slice(&keys).unwrap();
+        let bytes = filter.to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN + filter.size_in_bytes());
+        let loaded = Xor16::<u64>::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.fingerprints, filter.fingerprints);
+        assert!(keys.iter().all(|key| loaded.contains(key)));
+
+        assert!(matches!(
+            Xor8::<u64>::from_bytes(&bytes),
+            Err(DecodeError::Corrupt(_))
+        ));
+        assert!(matches!(
+            Xor16::<u64>::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(DecodeError::Truncated)
+        ));
+        let mut corrupt = bytes.clone();
+        corrupt[HEADER_LEN] ^= 1;
+        assert!(matches!(
+            Xor16::<u64>::from_bytes(&corrupt),
+            Err(DecodeError::ChecksumMismatch { .. })
+        ));
+        let mut corrupt = bytes;
+        corrupt[0] = b'B';
+        assert!(matches!(
+            Xor16::<u64>::from_bytes(&corrupt),
+            Err(DecodeError::BadMagic)
+        ));
+    }
+}
@@ -0,0 +1,156 @@
+use linked_list_1::hashtable::{HashMap, HashMapEntry, ProbeStrategy};
+
+#[test]
+fn insert_get_delete() {
+    let mut map = HashMap::<String, u64>::new(64);
+    for i in 0..20u64 {
+        map.insert(format!("key{}", i), i * i);
+    }
+    assert_eq!(map.amount(), 20);
+    assert_eq!(map.remaining_entries(), 44);
+    for i in 0..20u64 {
+        assert_eq!(map.get(&format!("key{}", i)), Some(&(i * i)));
+    }
+    assert_eq!(map.get(&"missing".to_string()), None);
+
+    let (key, value) = map.delete(&"key7".to_string()).unwrap();
+    assert_eq!((key.as_str(), value), ("key7", 49));
+    assert!(map.delete(&"key7".to_string()).is_none());
+    assert_eq!(map.get(&"key7".to_string()), None);
+    assert_eq!(map.amount(), 19);
+}
+
+#[test]
+fn overwrite_keeps_amount() {
+    let mut map = HashMap::new(16);
+    assert_eq!(map.insert(1u32, 'a'), None);
+    assert_eq!(map.insert(1u32, 'b'), Some('a'));
+    assert_eq!(map.insert(2u32, 'c'), None);
+    assert_eq!(map.get(&1), Some(&'b'));
+    assert_eq!(map.amount(), 2);
+}
+
+#[test]
+fn lookups_through_shared_reference() {
+    let mut map = HashMap::new(16);
+    map.insert("shared", 1);
+    let view: &HashMap<&str, i32> = &map;
+    assert_eq!(view.get(&"shared"), Some(&1));
+    assert_eq!(view.size(), 16);
+}
+
+#[test]
+fn grows_past_max_load_factor() {
+    let mut map = HashMap::<u8, u8>::new(2);
+    map.insert(1, 10);
+    map.insert(2, 20);
+    assert_eq!(map.size(), 4);
+    assert_eq!(map.remaining_entries(), 2);
+    map.extend(12);
+    assert_eq!(map.size(), 16);
+    assert_eq!(map.remaining_entries(), 14);
+    assert_eq!((map.get(&1), map.get(&2)), (Some(&10), Some(&20)));
+}
+
+#[test]
+fn entry_is_usable_on_its_own() {
+    let entry = HashMapEntry::new(String::from("k"), 3.5);
+    let copy = entry.clone();
+    assert_eq!(copy.key, "k");
+    assert_eq!(copy.value, 3.5);
+}
+
+#[test]
+fn owned_keys_and_values_move_in_and_out() {
+    let mut map = HashMap::<String, Vec<Box<str>>>::new(4);
+    for word in ["apple", "avocado", "banana", "blueberry", "cherry"] {
+        let key = word[..1].to_string();
+        match map.get_mut(&key) {
+            Some(words) => words.push(word.into()),
+            None => assert!(map.insert(key, vec![word.into()]).is_none()),
+        }
+    }
+    assert_eq!(map.amount(), 3);
+    assert_eq!(
+        map.get(&"b".to_string()).map(Vec::len),
+        Some(2),
+        "grouped under their first letter"
+    );
+
+    let previous = map.insert("c".to_string(), Vec::new()).unwrap();
+    assert_eq!(previous, vec![Box::from("cherry")]);
+    let (key, words) = map.delete(&"a".to_string()).unwrap();
+    assert_eq!(key, "a");
+    assert_eq!(words, vec![Box::from("apple"), Box::from("avocado")]);
+    assert!(map.get_mut(&"a".to_string()).is_none());
+}
+
+// Random inserts, deletes and lookups against std's HashMap. Keys come from a
+// small range so the operations keep running into each other.
+#[test]
+fn matches_std_hash_map() {
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    let strategies = [
+        ProbeStrategy::Linear,
+        ProbeStrategy::Triangular,
+        ProbeStrategy::DoubleHashing,
+        ProbeStrategy::Perturbation,
+    ];
+    for strategy in strategies {
+        for size in [1, 7, 16, 100] {
+            let mut map = HashMap::<u64, u64>::new(size);
+            map.set_probe_strategy(strategy);
+            let mut expected = std::collections::HashMap::new();
+            for step in 0..20_000u64 {
+                let key = next() % 200;
+                match next() % 3 {
+                    0 => {
+                        assert_eq!(map.insert(key, step), expected.insert(key, step));
+                    }
+                    1 => {
+                        assert_eq!(map.delete(&key), expected.remove_entry(&key));
+                    }
+                    _ => assert_eq!(map.get(&key), expected.get(&key)),
+                }
+                assert_eq!(map.amount(), expected.len());
+            }
+            for key in 0..200 {
+                assert_eq!(map.get(&key), expected.get(&key));
+            }
+        }
+    }
+}
+
+#[test]
+fn resizing_keeps_every_entry() {
+    let mut map = HashMap::with_capacity(4);
+    map.set_max_load_factor(0.9);
+    for i in 0..10_000u32 {
+        map.insert(i, i + 1);
+        assert!(map.load_factor() <= map.max_load_factor());
+    }
+    map.extend(1000);
+    assert!((0..10_000).all(|i| map.get(&i) == Some(&(i + 1))));
+
+    for i in 100..10_000 {
+        map.delete(&i);
+    }
+    map.shrink_to_fit();
+    // 112 slots, rounded up to a power of two
+    assert_eq!(map.size(), 128);
+    assert_eq!(map.amount(), 100);
+    assert!((0..100).all(|i| map.get(&i) == Some(&(i + 1))));
+
+    map.reserve(1000);
+    let size = map.size();
+    for i in 100..1100 {
+        map.insert(i, i + 1);
+    }
+    assert_eq!(map.size(), size);
+}